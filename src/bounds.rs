@@ -68,7 +68,8 @@ impl Bounds3 {
 
             t_min = t0.max(t_min);
             t_max = t1.min(t_max);
-            if t_max <= t_min {
+            // Equal distances still hit flat bounds, such as those around axis-aligned triangles
+            if t_max < t_min {
                 return false;
             }
         }
@@ -80,13 +81,18 @@ impl Bounds3 {
 impl Mul<Bounds3> for Mat4 {
     type Output = Bounds3;
 
+    // Transforms every corner so rotated bounds still enclose the original box
     fn mul(self, rhs: Bounds3) -> Self::Output {
-        let mut p_min = Vec4::new(rhs.p_min.x, rhs.p_min.y, rhs.p_min.z, 1.0);
-        let mut p_max = Vec4::new(rhs.p_max.x, rhs.p_max.y, rhs.p_max.z, 1.0);
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { rhs.p_min.x } else { rhs.p_max.x };
+            let y = if i & 2 == 0 { rhs.p_min.y } else { rhs.p_max.y };
+            let z = if i & 4 == 0 { rhs.p_min.z } else { rhs.p_max.z };
+            (self * Vec4::new(x, y, z, 1.0)).xyz()
+        };
 
-        p_min = self * p_min;
-        p_max = self * p_max;
-
-        Bounds3::new(p_min.xyz(), p_max.xyz())
+        let start = corner(0);
+        (1..8).fold(Bounds3::new(start, start), |bounds, i| {
+            bounds.union_point(corner(i))
+        })
     }
 }
@@ -0,0 +1,212 @@
+use crate::colour::Colour;
+use crate::utils::create_coordinates_system;
+use ultraviolet::Vec3;
+
+// Orthonormal basis around the shading normal. BSDFs work in this local frame, where the normal
+// is +z
+pub struct Frame {
+    pub s: Vec3,
+    pub t: Vec3,
+    pub n: Vec3,
+}
+
+impl Frame {
+    pub fn new(normal: &Vec3) -> Frame {
+        let (s, t) = create_coordinates_system(normal);
+
+        Frame { s, t, n: *normal }
+    }
+
+    // Frame with `s` along the part of `tangent` perpendicular to the normal, or an arbitrary one
+    // if the tangent is parallel to it
+    pub fn from_tangent(normal: &Vec3, tangent: &Vec3) -> Frame {
+        let s = *tangent - *normal * normal.dot(*tangent);
+        if s.mag_sq() < 1e-12 {
+            return Frame::new(normal);
+        }
+        let s = s.normalized();
+
+        Frame {
+            s,
+            t: normal.cross(s),
+            n: *normal,
+        }
+    }
+
+    #[inline]
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    #[inline]
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+pub struct BSDFSample {
+    // Incoming direction in the local frame
+    pub wi: Vec3,
+    pub f: Colour,
+    pub pdf: f32,
+    // Sampled from a delta lobe, so `f` and `pdf` are only meaningful as a ratio
+    pub specular: bool,
+}
+
+impl BSDFSample {
+    // Path throughput of the sampled direction, f * |cos(theta)| / pdf
+    pub fn throughput(&self) -> Colour {
+        self.f * abs_cos_theta(&self.wi) / self.pdf
+    }
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn cos_theta(w: &Vec3) -> f32 {
+    w.z
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn abs_cos_theta(w: &Vec3) -> f32 {
+    w.z.abs()
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn cos2_theta(w: &Vec3) -> f32 {
+    w.z * w.z
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn sin2_theta(w: &Vec3) -> f32 {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn sin_theta(w: &Vec3) -> f32 {
+    sin2_theta(w).sqrt()
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn tan2_theta(w: &Vec3) -> f32 {
+    sin2_theta(w) / cos2_theta(w)
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn same_hemisphere(w: &Vec3, wp: &Vec3) -> bool {
+    w.z * wp.z > 0.0
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn reflect(wo: &Vec3, n: &Vec3) -> Vec3 {
+    -*wo + *n * 2.0 * wo.dot(*n)
+}
+
+// Schlick's approximation of the Fresnel reflectance, from the reflectance at normal incidence
+#[allow(dead_code)]
+#[inline]
+pub fn fresnel_schlick(f0: Colour, cos_theta: f32) -> Colour {
+    let weight = (1.0 - cos_theta.abs()).clamp(0.0, 1.0).powi(5);
+
+    f0 + (Colour::new(1.0, 1.0, 1.0) - f0) * weight
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction `eta + ik` per channel,
+// relative to the medium the light arrives from
+#[allow(dead_code)]
+pub fn fresnel_conductor(cos_theta_i: f32, eta: Colour, k: Colour) -> Colour {
+    let cos_theta_i = cos_theta_i.abs().min(1.0) as f64;
+    let channel = |eta: f64, k: f64| {
+        let cos2_theta_i = cos_theta_i * cos_theta_i;
+        let sin2_theta_i = 1.0 - cos2_theta_i;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2_theta_i;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2_theta_i;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i * a;
+        let r_perpendicular = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2_theta_i * a2_plus_b2 + sin2_theta_i * sin2_theta_i;
+        let t4 = t2 * sin2_theta_i;
+        let r_parallel = r_perpendicular * (t3 - t4) / (t3 + t4);
+
+        (r_parallel + r_perpendicular) / 2.0
+    };
+
+    Colour::new(
+        channel(eta.r, k.r),
+        channel(eta.g, k.g),
+        channel(eta.b, k.b),
+    )
+}
+
+// How much light a metal reflects at each angle
+#[derive(Copy, Clone, Debug)]
+pub enum Fresnel {
+    // Artist-friendly tint, the reflectance at normal incidence
+    Schlick(Colour),
+    Conductor { eta: Colour, k: Colour },
+}
+
+impl Fresnel {
+    pub fn evaluate(&self, cos_theta_i: f32) -> Colour {
+        match self {
+            Fresnel::Schlick(f0) => fresnel_schlick(*f0, cos_theta_i),
+            Fresnel::Conductor { eta, k } => fresnel_conductor(cos_theta_i, *eta, *k),
+        }
+    }
+}
+
+// Unpolarised Fresnel reflectance of a dielectric interface, where `eta` is the ratio of the
+// transmitted to the incident index of refraction. Returns 1 for total internal reflection
+#[allow(dead_code)]
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i.powi(2)) / eta.powi(2);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+
+    (r_parallel.powi(2) + r_perpendicular.powi(2)) / 2.0
+}
+
+// Fraction of uniformly diffuse light that `fresnel_dielectric` reflects, found by integrating it
+// over the hemisphere with the midpoint rule
+pub fn diffuse_fresnel_reflectance(eta: f32) -> f32 {
+    const STEPS: usize = 1024;
+    let sum: f32 = (0..STEPS)
+        .map(|i| {
+            let cos_theta = (i as f32 + 0.5) / STEPS as f32;
+            fresnel_dielectric(cos_theta, eta) * cos_theta
+        })
+        .sum();
+
+    2.0 * sum / STEPS as f32
+}
+
+// Refracts `wo` through a surface with normal `n` on the same side as `wo`, with `eta` as in
+// `fresnel_dielectric`. Returns `None` for total internal reflection
+#[allow(dead_code)]
+pub fn refract(wo: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_theta_i = n.dot(*wo);
+    let sin2_theta_t = (1.0 - cos_theta_i.powi(2)).max(0.0) / eta.powi(2);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    Some(-*wo / eta + *n * (cos_theta_i / eta - cos_theta_t))
+}
@@ -0,0 +1,101 @@
+use crate::bsdf::Frame;
+use crate::intersectable::IntersectRecord;
+use crate::texture::Texture;
+use std::sync::Arc;
+use ultraviolet::{Vec2, Vec3};
+
+// Ways of adding detail to a surface by moving its shading normal, without moving the surface
+pub enum NormalModifier {
+    // Height field from a scalar texture, scaled by `scale` into scene units, which the surface
+    // is shaded as if it were displaced along its normal by
+    Bump {
+        height: Arc<dyn Texture>,
+        scale: f32,
+    },
+    // Normals in the tangent space around the shading normal, with x along `dpdu` and y along
+    // `dpdv`, each mapped from [-1, 1] to a colour channel in [0, 1]
+    NormalMap(Arc<dyn Texture>),
+}
+
+impl NormalModifier {
+    // Replaces the shading normal of `rec`, leaving the geometric normal alone. The new normal is
+    // kept on the same side as the geometric one
+    pub fn apply(&self, rec: &mut IntersectRecord) {
+        let perturbed = match self {
+            NormalModifier::Bump { height, scale } => bump(rec, height.as_ref(), *scale),
+            NormalModifier::NormalMap(texture) => normal_map(rec, texture.as_ref()),
+        };
+        let length_sq = perturbed.mag_sq();
+        if length_sq == 0.0 || !length_sq.is_finite() {
+            return;
+        }
+
+        let perturbed = perturbed.normalized();
+        rec.shading_normal = if perturbed.dot(rec.normal) < 0.0 {
+            -perturbed
+        } else {
+            perturbed
+        };
+    }
+}
+
+// Finds the slope of the height field by finite differences over about the hit's footprint, as
+// in pbrt. The change in the normal across the displaced surface is ignored, as it's negligible
+// for small heights
+fn bump(rec: &IntersectRecord, height: &dyn Texture, scale: f32) -> Vec3 {
+    let shift = |dx: f32, dy: f32| {
+        let shift = 0.5 * (dx.abs() + dy.abs());
+        if shift > 0.0 {
+            shift
+        } else {
+            0.0005
+        }
+    };
+    let differentials = &rec.differentials;
+    let du = shift(differentials.dudx, differentials.dudy);
+    let dv = shift(differentials.dvdx, differentials.dvdy);
+
+    let displacement = |du: f32, dv: f32| {
+        let shifted = IntersectRecord {
+            point: rec.point + rec.dpdu * du + rec.dpdv * dv,
+            object_point: rec.object_point + rec.object_dpdu * du + rec.object_dpdv * dv,
+            uv: rec.uv + Vec2::new(du, dv),
+            ..*rec
+        };
+        height.evaluate_scalar(&shifted) as f32 * scale
+    };
+    let base = displacement(0.0, 0.0);
+    let dddu = (displacement(du, 0.0) - base) / du;
+    let dddv = (displacement(0.0, dv) - base) / dv;
+
+    let normal = rec.shading_normal;
+    let dpdu = rec.dpdu + normal * dddu;
+    let dpdv = rec.dpdv + normal * dddv;
+
+    // The cross product faces whichever way the parameterisation's handedness makes it
+    let perturbed = dpdu.cross(dpdv);
+    if perturbed.dot(normal) < 0.0 {
+        -perturbed
+    } else {
+        perturbed
+    }
+}
+
+fn normal_map(rec: &IntersectRecord, texture: &dyn Texture) -> Vec3 {
+    let colour = texture.evaluate(rec);
+    let local = Vec3::new(
+        2.0 * colour.r as f32 - 1.0,
+        2.0 * colour.g as f32 - 1.0,
+        2.0 * colour.b as f32 - 1.0,
+    );
+
+    // The frame's second tangent follows the normal, so it's turned to run along `dpdv`
+    let frame = Frame::from_tangent(&rec.shading_normal, &rec.dpdu);
+    let bitangent = if frame.t.dot(rec.dpdv) < 0.0 {
+        -frame.t
+    } else {
+        frame.t
+    };
+
+    frame.s * local.x + bitangent * local.y + frame.n * local.z
+}
@@ -36,9 +36,9 @@ impl BVHNode {
         }
     }
 
-    pub fn construct(shapes: &mut Vec<Box<dyn Shape>>) -> BVHNode {
+    pub fn construct(shapes: &mut [Box<dyn Shape>]) -> BVHNode {
         let len = shapes.len();
-        Self::construct_recurse(&mut shapes[..], 0, len)
+        Self::construct_recurse(shapes, 0, len)
     }
 
     fn construct_recurse(shapes: &mut [Box<dyn Shape>], start: usize, end: usize) -> BVHNode {
@@ -80,50 +80,47 @@ impl BVHNode {
         }
     }
 
+    // Also returns the index of the shape that was hit
     pub fn intersect(
         &self,
         shapes: &[Box<dyn Shape>],
         ray: &Ray,
         test_alpha_textures: bool,
-    ) -> Option<(IntersectRecord, f32)> {
+    ) -> Option<(IntersectRecord, f32, usize)> {
         if self.bounds.intersect(ray) {
             let mut distance = ray.t_max;
             let mut record = None;
 
             return if let Some(left) = &self.left {
-                if let Some((rec, dist)) = left.intersect(shapes, ray, test_alpha_textures) {
+                if let Some((rec, dist, index)) = left.intersect(shapes, ray, test_alpha_textures) {
                     distance = dist;
-                    record = Some(rec);
+                    record = Some((rec, index));
                 }
 
                 if let Some(right) = &self.right {
                     let ray = Ray::new(ray.origin, ray.direction, ray.t_min, distance);
-                    if let Some((rec, dist)) = right.intersect(shapes, &ray, test_alpha_textures) {
+                    if let Some((rec, dist, index)) =
+                        right.intersect(shapes, &ray, test_alpha_textures)
+                    {
                         if dist < distance {
                             distance = dist;
-                            record = Some(rec);
+                            record = Some((rec, index));
                         }
                     }
                 }
 
-                match record {
-                    Some(rec) => Some((rec, distance)),
-                    None => None,
-                }
+                record.map(|(rec, index)| (rec, distance, index))
             } else {
-                for shape in shapes[self.offset..self.offset + self.count].iter() {
+                for (index, shape) in shapes.iter().enumerate().skip(self.offset).take(self.count) {
                     if let Some((rec, dist)) = shape.intersect(ray, test_alpha_textures) {
                         if dist < distance {
                             distance = dist;
-                            record = Some(rec);
+                            record = Some((rec, index));
                         }
                     }
                 }
 
-                match record {
-                    Some(rec) => Some((rec, distance)),
-                    None => None,
-                }
+                record.map(|(rec, index)| (rec, distance, index))
             };
         }
 
@@ -1,4 +1,4 @@
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use rand::Rng;
 use rand_distr::{Distribution, UnitDisc};
 use ultraviolet::Vec3;
@@ -66,19 +66,56 @@ impl Camera {
 
     // TODO: Use matrix transforms instead
     pub fn get_ray<R: Rng + ?Sized>(&self, s: f64, t: f64, sampler: &mut R) -> Ray {
-        let rand_unit_disc: [f32; 2] = self.unit_disc.sample(sampler);
-        let rand_unit_disc = Vec3::new(rand_unit_disc[0], rand_unit_disc[1], 0.0);
-        let rd = rand_unit_disc * self.lens_radius;
-        let offset = self.u * rd.x + self.v * rd.y;
+        let offset = self.lens_offset(sampler);
 
         Ray::new(
             self.origin + offset,
-            (self.lower_left_corner + self.horizontal * s as f32 + self.vertical * t as f32
-                - self.origin
-                - offset)
-                .normalized(),
+            self.direction(s, t, &offset),
             self.t_min,
             self.t_max,
         )
     }
+
+    // Like `get_ray`, with differentials for rays `ds` and `dt` further across the viewport
+    // through the same point on the lens
+    pub fn get_ray_differential<R: Rng + ?Sized>(
+        &self,
+        s: f64,
+        t: f64,
+        ds: f64,
+        dt: f64,
+        sampler: &mut R,
+    ) -> Ray {
+        let offset = self.lens_offset(sampler);
+        let origin = self.origin + offset;
+
+        Ray {
+            differential: Some(RayDifferential {
+                rx_origin: origin,
+                rx_direction: self.direction(s + ds, t, &offset),
+                ry_origin: origin,
+                ry_direction: self.direction(s, t + dt, &offset),
+            }),
+            ..Ray::new(
+                origin,
+                self.direction(s, t, &offset),
+                self.t_min,
+                self.t_max,
+            )
+        }
+    }
+
+    fn lens_offset<R: Rng + ?Sized>(&self, sampler: &mut R) -> Vec3 {
+        let rand_unit_disc: [f32; 2] = self.unit_disc.sample(sampler);
+        let rd = Vec3::new(rand_unit_disc[0], rand_unit_disc[1], 0.0) * self.lens_radius;
+
+        self.u * rd.x + self.v * rd.y
+    }
+
+    fn direction(&self, s: f64, t: f64, offset: &Vec3) -> Vec3 {
+        (self.lower_left_corner + self.horizontal * s as f32 + self.vertical * t as f32
+            - self.origin
+            - *offset)
+            .normalized()
+    }
 }
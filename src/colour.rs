@@ -16,7 +16,7 @@ impl Colour {
         Colour { r, g, b }
     }
 
-    pub fn new_f32(r: f64, g: f64, b: f64) -> Colour {
+    pub fn new_f32(r: f32, g: f32, b: f32) -> Colour {
         Colour {
             r: r as f64,
             g: g as f64,
@@ -33,7 +33,32 @@ impl Colour {
         }
     }
 
-    pub fn to_u8(&self) -> [u8; 3] {
+    pub fn is_black(&self) -> bool {
+        self.r == 0.0 && self.g == 0.0 && self.b == 0.0
+    }
+
+    // Relative luminance of linear sRGB
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn lerp(&self, other: Colour, t: f64) -> Colour {
+        *self * (1.0 - t) + other * t
+    }
+
+    pub fn sqrt(&self) -> Colour {
+        Colour::new(self.r.sqrt(), self.g.sqrt(), self.b.sqrt())
+    }
+
+    pub fn clamp(&self, min: f64, max: f64) -> Colour {
+        Colour::new(
+            clamp(self.r, min, max),
+            clamp(self.g, min, max),
+            clamp(self.b, min, max),
+        )
+    }
+
+    pub fn to_u8(self) -> [u8; 3] {
         [
             (256. * clamp(self.r, 0.0, 0.999)) as u8,
             (256. * clamp(self.g, 0.0, 0.999)) as u8,
@@ -52,9 +77,9 @@ impl Colour {
     }
 }
 
-impl Into<Vec3> for Colour {
-    fn into(self) -> Vec3 {
-        Vec3::new(self.r as f32, self.g as f32, self.b as f32)
+impl From<Colour> for Vec3 {
+    fn from(c: Colour) -> Self {
+        Vec3::new(c.r as f32, c.g as f32, c.b as f32)
     }
 }
 
@@ -1,9 +1,11 @@
 use crate::bounds::Bounds3;
-use crate::intersectable::{IntersectRecord, Intersectable};
+use crate::intersectable::{IntersectRecord, Intersectable, SurfaceDifferentials};
 use crate::material::MaterialID;
 use crate::ray::Ray;
-use crate::shape::Shape;
-use crate::utils::{quadratic, transform_swaps_handedness};
+use crate::shape::{area_scale, Shape};
+use crate::transform::{transform_normal, transform_point, transform_vector};
+use crate::utils::{quadratic, transform_swaps_handedness, uniform_scale};
+use std::f32::consts::PI;
 use ultraviolet::{Mat4, Rotor3, Vec2, Vec3, Vec4};
 
 pub struct Cylinder {
@@ -16,6 +18,7 @@ pub struct Cylinder {
     world_to_object: Mat4,
     reverse_orientation: bool,
     transform_swaps_handedness: bool,
+    area: f32,
 }
 
 #[allow(dead_code)]
@@ -53,7 +56,7 @@ impl Cylinder {
 
         let transform_swaps_handedness = transform_swaps_handedness(&object_to_world);
 
-        Cylinder {
+        let mut cylinder = Cylinder {
             radius,
             z_min,
             z_max,
@@ -62,7 +65,11 @@ impl Cylinder {
             world_to_object,
             reverse_orientation,
             transform_swaps_handedness,
-        }
+            area: 0.0,
+        };
+        cylinder.area = cylinder.world_area();
+
+        cylinder
     }
 
     pub fn from_transform(
@@ -76,7 +83,7 @@ impl Cylinder {
     ) -> Cylinder {
         let transform_swaps_handedness = transform_swaps_handedness(&object_to_world);
 
-        Cylinder {
+        let mut cylinder = Cylinder {
             radius,
             z_min,
             z_max,
@@ -85,6 +92,77 @@ impl Cylinder {
             world_to_object,
             reverse_orientation,
             transform_swaps_handedness,
+            area: 0.0,
+        };
+        cylinder.area = cylinder.world_area();
+
+        cylinder
+    }
+
+    fn object_area(&self) -> f32 {
+        (self.z_max - self.z_min) * self.radius * 2.0 * PI
+    }
+
+    // A transform stretches the side by the same amount all along its length, so only the angle
+    // around it needs integrating when the cylinder is squashed
+    fn world_area(&self) -> f32 {
+        if let Some(scale) = uniform_scale(&self.object_to_world) {
+            return self.object_area() * scale.powi(2);
+        }
+
+        const STEPS: usize = 256;
+        let mut sum = 0.0;
+        for i in 0..STEPS {
+            let phi = (i as f32 + 0.5) / STEPS as f32 * 2.0 * PI;
+            sum += area_scale(self, &Vec3::new(phi.cos(), phi.sin(), 0.0));
+        }
+
+        self.object_area() * sum / STEPS as f32
+    }
+
+    // World space normal from the outward normal in object space. Transforming normals keeps them
+    // outward even when the transform swaps handedness, so only the orientation flips them
+    fn orient(&self, local_normal: Vec3) -> Vec3 {
+        let normal = transform_normal(&self.world_to_object, local_normal).normalized();
+        if self.reverse_orientation {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    // Record for a point on the surface. `u` goes around the z axis from +x and `v` along it from
+    // `z_min`, as in pbrt
+    fn record(
+        &self,
+        point: Vec3,
+        local_point: Vec3,
+        normal: Vec3,
+        front_face: bool,
+    ) -> IntersectRecord {
+        let mut phi = local_point.y.atan2(local_point.x);
+        if phi < 0.0 {
+            phi += 2.0 * PI;
+        }
+        let height = self.z_max - self.z_min;
+
+        let dpdu = Vec3::new(-2.0 * PI * local_point.y, 2.0 * PI * local_point.x, 0.0);
+        let dpdv = Vec3::new(0.0, 0.0, height);
+
+        IntersectRecord {
+            point,
+            object_point: local_point,
+            normal,
+            shading_normal: normal,
+            uv: Vec2::new(phi / (2.0 * PI), (local_point.z - self.z_min) / height),
+            dpdu: transform_vector(&self.object_to_world, dpdu),
+            dpdv: transform_vector(&self.object_to_world, dpdv),
+            object_dpdu: dpdu,
+            object_dpdv: dpdv,
+            differentials: SurfaceDifferentials::default(),
+            vertex_colour: None,
+            front_face,
+            material_id: self.material_id,
         }
     }
 }
@@ -118,28 +196,20 @@ impl Intersectable for Cylinder {
             let point = ray.at(t_hit);
 
             // Calculate normal by projecting the surface point to the inner centre line and
-            // finding the vector between them
+            // finding the vector from it
             let a = Vec3::new(0.0, 0.0, z_min);
             let b = Vec3::new(0.0, 0.0, z_max);
             let local_point = r.at(t_hit);
             let ap = local_point - a;
             let ab = b - a;
             let projected = a + (ap.dot(ab) / ab.dot(ab)) * ab;
-            let local_normal = projected - local_point;
-
-            let normal = (self.object_to_world
-                * Vec4::new(local_normal.x, local_normal.y, local_normal.z, 0.0))
-            .xyz()
-            .normalized();
-
-            return Some((
-                IntersectRecord {
-                    point,
-                    normal,
-                    material_id: self.material_id,
-                },
-                t_hit,
-            ));
+            let local_normal = local_point - projected;
+
+            let normal = self.orient(local_normal);
+
+            let front_face = ray.direction.dot(normal) < 0.0;
+
+            return Some((self.record(point, local_point, normal, front_face), t_hit));
         }
 
         None
@@ -174,23 +244,33 @@ impl Shape for Cylinder {
         self.transform_swaps_handedness
     }
 
-    fn area(&self) -> f32 {
-        (self.z_max - self.z_min) * self.radius
+    fn material_id(&self) -> MaterialID {
+        self.material_id
     }
 
-    fn pdf_wi(&self, _rec: &IntersectRecord, _wi: &Vec3) -> f32 {
-        unimplemented!()
+    fn area(&self) -> f32 {
+        self.area
     }
 
-    fn pdf(&self, _rec: &IntersectRecord) -> f32 {
-        unimplemented!()
+    // Points are sampled evenly over the object space side, so the density in world space falls
+    // where the transform stretches it
+    fn pdf(&self, rec: &IntersectRecord) -> f32 {
+        let local = rec.object_point;
+        let normal = Vec3::new(local.x, local.y, 0.0).normalized();
+        1.0 / (self.object_area() * area_scale(self, &normal))
     }
 
-    fn sample(&self, _point: &Vec2) -> IntersectRecord {
-        unimplemented!()
-    }
+    fn sample(&self, point: &Vec2) -> IntersectRecord {
+        let z = self.z_min + point.x * (self.z_max - self.z_min);
+        let phi = point.y * 2.0 * PI;
+        let local_normal = Vec3::new(phi.cos(), phi.sin(), 0.0);
+        let local_point = Vec3::new(self.radius * phi.cos(), self.radius * phi.sin(), z);
 
-    fn sample_record(&self, _rec: &IntersectRecord, _u: &Vec2) -> IntersectRecord {
-        unimplemented!()
+        self.record(
+            transform_point(&self.object_to_world, local_point),
+            local_point,
+            self.orient(local_normal),
+            true,
+        )
     }
 }
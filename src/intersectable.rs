@@ -1,16 +1,119 @@
+use crate::bsdf::Frame;
+use crate::colour::Colour;
 use crate::material::MaterialID;
-use crate::ray::Ray;
-use ultraviolet::Vec3;
+use crate::ray::{Ray, RayDifferential};
+use ultraviolet::{Vec2, Vec3};
 
 pub struct IntersectRecord {
     pub point: Vec3,
+    // The point in the shape's object space, for textures that move with the shape
+    pub object_point: Vec3,
+    // Geometric normal facing out of the shape, or into it if the shape's orientation is reversed,
+    // whichever side was hit
     pub normal: Vec3,
+    // Normal that materials are shaded around, like the interpolated vertex normals of a mesh.
+    // Oriented the same way as `normal`
+    pub shading_normal: Vec3,
+    // Surface parameterisation and the derivatives of the point along it
+    pub uv: Vec2,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // The same derivatives in object space, so `object_point` can be moved along the surface
+    pub object_dpdu: Vec3,
+    pub object_dpdv: Vec3,
+    pub differentials: SurfaceDifferentials,
+    // Colour interpolated from the vertices, for meshes that have vertex colours
+    pub vertex_colour: Option<Colour>,
+    // Whether the ray hit the side the normal faces. Always true for sampled points
+    pub front_face: bool,
     pub material_id: MaterialID,
 }
 
+// How far the hit point and its UVs move between neighbouring pixels, all zero when the ray
+// didn't have differentials
+#[derive(Copy, Clone, Default)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
+impl IntersectRecord {
+    // Local frame for BSDFs, lined up with `dpdu` so anisotropic materials follow the surface
+    pub fn shading_frame(&self) -> Frame {
+        Frame::from_tangent(&self.shading_normal, &self.dpdu)
+    }
+
+    // Intersects the ray's offset rays with the tangent plane at the hit, and finds the UVs there
+    // from the surface derivatives
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        self.differentials = ray
+            .differential
+            .and_then(|differential| self.surface_differentials(&differential))
+            .unwrap_or_default();
+    }
+
+    fn surface_differentials(&self, ray: &RayDifferential) -> Option<SurfaceDifferentials> {
+        let normal = self.normal;
+        let plane = normal.dot(self.point);
+        let tx = (plane - normal.dot(ray.rx_origin)) / normal.dot(ray.rx_direction);
+        let ty = (plane - normal.dot(ray.ry_origin)) / normal.dot(ray.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return None;
+        }
+        let dpdx = ray.rx_origin + ray.rx_direction * tx - self.point;
+        let dpdy = ray.ry_origin + ray.ry_direction * ty - self.point;
+
+        // Solve dp = dpdu * du + dpdv * dv in the two axes the normal is furthest from
+        let axes = if normal.x.abs() > normal.y.abs() && normal.x.abs() > normal.z.abs() {
+            [1, 2]
+        } else if normal.y.abs() > normal.z.abs() {
+            [0, 2]
+        } else {
+            [0, 1]
+        };
+        let component = |v: &Vec3, axis: usize| match axis {
+            0 => v.x,
+            1 => v.y,
+            _ => v.z,
+        };
+        let [a, b] = axes.map(|axis| component(&self.dpdu, axis));
+        let [c, d] = axes.map(|axis| component(&self.dpdv, axis));
+        let determinant = a * d - c * b;
+        let solve = |dp: &Vec3| {
+            if determinant.abs() < 1e-12 {
+                return (0.0, 0.0);
+            }
+            let [e, f] = axes.map(|axis| component(dp, axis));
+            let du = (d * e - c * f) / determinant;
+            let dv = (a * f - b * e) / determinant;
+            if du.is_finite() && dv.is_finite() {
+                (du, dv)
+            } else {
+                (0.0, 0.0)
+            }
+        };
+        let (dudx, dvdx) = solve(&dpdx);
+        let (dudy, dvdy) = solve(&dpdy);
+
+        Some(SurfaceDifferentials {
+            dpdx,
+            dpdy,
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        })
+    }
+}
+
 pub trait Intersectable: Send + Sync {
     fn intersect(&self, ray: &Ray, test_alpha_textures: bool) -> Option<(IntersectRecord, f32)>;
 
+    #[allow(dead_code)]
     fn intersect_predicate(&self, ray: &Ray, test_alpha_textures: bool) -> bool {
         self.intersect(ray, test_alpha_textures).is_some()
     }
@@ -1,30 +1,44 @@
-use rand::prelude::ThreadRng;
+use rand::rngs::StdRng;
 use rand::Rng;
 use rayon::prelude::*;
 use std::io::Write;
-use ultraviolet::{Mat4, Rotor3, Vec3};
+use ultraviolet::{Mat4, Vec2, Vec3};
 
+use crate::bsdf::{abs_cos_theta, BSDFSample};
 use crate::camera::Camera;
 use crate::colour::Colour;
-use crate::cylinder::Cylinder;
-use crate::intersectable::Intersectable;
-use crate::material::{Diffuse, Emissive};
-use crate::ray::Ray;
+use crate::intersectable::{IntersectRecord, Intersectable};
+use crate::material::{Diffuse, Emissive, Material, Medium};
+use crate::ray::{Ray, RayDifferential};
 use crate::scene::Scene;
+use crate::settings::{Integrator, SceneSource, Settings};
 use crate::sphere::Sphere;
-use std::f32::consts::PI;
+use crate::utils::{power_heuristic, uniform_sample_sphere};
 
 mod bounds;
+mod bsdf;
+mod bump;
 mod bvh;
 mod camera;
 mod colour;
 mod cylinder;
 mod intersectable;
 mod material;
+mod microfacet;
+mod noise;
+mod obj;
+mod pbrt;
+mod ply;
+mod principled;
 mod ray;
 mod scene;
+mod scene_file;
+mod settings;
 mod shape;
 mod sphere;
+mod texture;
+mod transform;
+mod triangle;
 mod utils;
 
 #[allow(dead_code)]
@@ -33,18 +47,20 @@ fn debug_normals(
     scene: &Scene,
     depth: u32,
     stop_depth: u32,
-    rng: &mut ThreadRng,
+    rng: &mut StdRng,
 ) -> Colour {
     if depth == 0 {
         return Colour::default();
     }
 
-    if let Some((rec, _)) = scene.intersect(&ray, true) {
-        if depth == stop_depth {
-            return Colour::from(Vec3::new(0.5, 0.5, 0.5) + rec.normal * 0.5);
-        }
+    if let Some((mut rec, _)) = scene.intersect(ray, true) {
+        rec.compute_differentials(ray);
         if let Some(material) = scene.materials.get(rec.material_id) {
-            if let Some((scattered, _)) = material.scatter(ray, &rec, rng) {
+            material.perturb(&mut rec);
+            if depth == stop_depth {
+                return Colour::from(Vec3::new(0.5, 0.5, 0.5) + rec.shading_normal * 0.5);
+            }
+            if let Some((scattered, _)) = scatter(ray, &rec, material, rng) {
                 return debug_normals(&scattered, scene, depth - 1, stop_depth, rng);
             }
         } else {
@@ -55,38 +71,347 @@ fn debug_normals(
     Colour::default()
 }
 
-fn cast_ray(ray: &Ray, scene: &Scene, depth: u32, rng: &mut ThreadRng) -> Colour {
+// Samples the material's BSDF, returning the scattered ray in world space
+fn scatter(
+    ray: &Ray,
+    rec: &IntersectRecord,
+    material: &dyn Material,
+    rng: &mut StdRng,
+) -> Option<(Ray, BSDFSample)> {
+    let frame = rec.shading_frame();
+    let wo = frame.to_local(&-ray.direction);
+    let sample = material.sample_f(&wo, rec, rng)?;
+    if sample.pdf == 0.0 {
+        return None;
+    }
+    let wi = frame.to_world(&sample.wi);
+    if !same_sides(rec, &-ray.direction, &wi, &wo, &sample.wi) {
+        return None;
+    }
+    let mut scattered = Ray::new(rec.point, wi, ray.t_min, ray.t_max);
+    // Other bounces spread the footprint too widely for the differentials to be useful
+    if sample.specular {
+        scattered.differential = specular_differential(ray, rec, &scattered.direction);
+    }
+
+    Some((scattered, sample))
+}
+
+// Whether `wo` and `wi` are on the same side of the geometric surface as they are of the shading
+// frame, given in world and local space. Where the shading normal has been moved from the
+// geometric one, light can otherwise reflect through the surface or transmit back out of it
+fn same_sides(
+    rec: &IntersectRecord,
+    wo: &Vec3,
+    wi: &Vec3,
+    wo_local: &Vec3,
+    wi_local: &Vec3,
+) -> bool {
+    let geometric = wo.dot(rec.normal) * wi.dot(rec.normal) > 0.0;
+    let shading = wo_local.z * wi_local.z > 0.0;
+
+    geometric == shading
+}
+
+// Differentials of a mirror reflection or refraction into `wi`, treating the surface as flat over
+// the footprint, so curved mirrors and lenses get less blur than they should
+fn specular_differential(ray: &Ray, rec: &IntersectRecord, wi: &Vec3) -> Option<RayDifferential> {
+    let differential = ray.differential?;
+    let wo = -ray.direction.normalized();
+    let dwodx = -differential.rx_direction - wo;
+    let dwody = -differential.ry_direction - wo;
+    let mut normal = rec.shading_normal;
+    if wo.dot(normal) < 0.0 {
+        normal = -normal;
+    }
+
+    let (rx_direction, ry_direction) = if wi.dot(normal) > 0.0 {
+        (
+            *wi - dwodx + normal * (2.0 * dwodx.dot(normal)),
+            *wi - dwody + normal * (2.0 * dwody.dot(normal)),
+        )
+    } else {
+        // Snell's law gives the relative index of refraction from the tangential components,
+        // which vanish at normal incidence where it makes no difference to the direction
+        let cos_o = wo.dot(normal);
+        let cos_i = wi.dot(normal).abs();
+        let sin_o = (1.0 - cos_o.powi(2)).max(0.0).sqrt();
+        let sin_i = (1.0 - cos_i.powi(2)).max(0.0).sqrt();
+        let eta = if sin_o > 1e-3 { sin_i / sin_o } else { 1.0 };
+
+        let dmu = |dwo: &Vec3| (eta - eta.powi(2) * cos_o / cos_i) * dwo.dot(normal);
+        (
+            *wi - dwodx * eta + normal * dmu(&dwodx),
+            *wi - dwody * eta + normal * dmu(&dwody),
+        )
+    };
+
+    Some(RayDifferential {
+        rx_origin: rec.point + rec.differentials.dpdx,
+        rx_direction,
+        ry_origin: rec.point + rec.differentials.dpdy,
+        ry_direction,
+    })
+}
+
+// Like `scatter`, but light refracted into a material's interior is followed until it leaves the
+// shape again, where `object` is the index of the shape that was hit. Returns the ray leaving the
+// surface, its throughput, and the BSDF sample that started it
+fn scatter_through(
+    ray: &Ray,
+    rec: &IntersectRecord,
+    object: usize,
+    material: &dyn Material,
+    scene: &Scene,
+    rng: &mut StdRng,
+) -> Option<(Ray, Colour, BSDFSample)> {
+    let (scattered, sample) = scatter(ray, rec, material, rng)?;
+    let throughput = sample.throughput();
+
+    match material.interior(rec) {
+        Some(medium) if scattered.direction.dot(rec.normal) < 0.0 => {
+            let (exit, walk_throughput) = random_walk(&scattered, &medium, object, scene, rng)?;
+            Some((exit, throughput * walk_throughput, sample))
+        }
+        _ => Some((scattered, throughput, sample)),
+    }
+}
+
+// Walks are cut short after this many scattering events, losing the light still inside
+const MAX_WALK_STEPS: u32 = 1024;
+
+// Random walk through a homogeneous medium from a ray that just entered the shape `object`, until
+// the walk reaches its boundary and scatters out. Other shapes inside it are passed through.
+// Distances are sampled with the extinction of a channel chosen in proportion to its throughput so
+// far, and weighted by the combined pdf over all channels. Choosing channels uniformly instead lets
+// the weights of coloured media blow up
+fn random_walk(
+    ray: &Ray,
+    medium: &Medium,
+    object: usize,
+    scene: &Scene,
+    rng: &mut StdRng,
+) -> Option<(Ray, Colour)> {
+    let channels = |c: &Colour| [c.r, c.g, c.b];
+    let sigma_t = channels(&medium.sigma_t);
+    let surface = scene.objects[object].surface();
+    let mut ray = ray.clone();
+    let mut throughput = Colour::new(1.0, 1.0, 1.0);
+
+    for _ in 0..MAX_WALK_STEPS {
+        if throughput.is_black() {
+            return None;
+        }
+
+        let weights = channels(&throughput);
+        let total: f64 = weights.iter().sum();
+        let probabilities = weights.map(|weight| weight / total);
+        let u: f64 = rng.gen();
+        let channel = if u < probabilities[0] {
+            0
+        } else if u < probabilities[0] + probabilities[1] {
+            1
+        } else {
+            2
+        };
+
+        let distance = -(1.0 - rng.gen::<f64>()).ln() / sigma_t[channel];
+        let mut step = Ray::new(ray.origin, ray.direction, ray.t_min, distance as f32);
+        let hit = loop {
+            match scene.intersect_object(&step, true) {
+                Some((_, t_hit, index)) if scene.objects[index].surface() != surface => {
+                    step.t_min = t_hit;
+                }
+                hit => break hit.map(|(rec, t_hit, _)| (rec, t_hit)),
+            }
+        };
+
+        let distance = match &hit {
+            Some((_, t_hit)) => *t_hit as f64,
+            None => distance,
+        };
+        let transmittance = sigma_t.map(|sigma_t| (-sigma_t * distance).exp());
+
+        match hit {
+            // Left the medium, so the chance was of sampling any distance beyond the boundary
+            Some((mut rec, _)) => {
+                let pdf: f64 = (0..3).map(|i| probabilities[i] * transmittance[i]).sum();
+                throughput *=
+                    Colour::new(transmittance[0], transmittance[1], transmittance[2]) / pdf;
+
+                let material = scene.materials.get(rec.material_id)?;
+                material.perturb(&mut rec);
+                // `step` only reaches as far as the free flight, which the ray leaving shouldn't
+                let (scattered, sample) = scatter(&ray, &rec, material, rng)?;
+                throughput *= sample.throughput();
+                if scattered.direction.dot(rec.normal) > 0.0 {
+                    return Some((scattered, throughput));
+                }
+                // Reflected back inside
+                ray = scattered;
+            }
+            None => {
+                let pdf: f64 = (0..3)
+                    .map(|i| probabilities[i] * sigma_t[i] * transmittance[i])
+                    .sum();
+                throughput *= medium.sigma_s
+                    * Colour::new(transmittance[0], transmittance[1], transmittance[2])
+                    / pdf;
+                let direction = uniform_sample_sphere(&Vec2::new(rng.gen(), rng.gen()));
+                ray = Ray::new(step.at(distance as f32), direction, ray.t_min, ray.t_max);
+            }
+        }
+    }
+
+    None
+}
+
+// Samples a point on a randomly chosen light and returns its unoccluded contribution, weighted
+// against the chance of scattering finding the same light
+fn sample_light(
+    ray: &Ray,
+    rec: &IntersectRecord,
+    material: &dyn Material,
+    scene: &Scene,
+    rng: &mut StdRng,
+) -> Colour {
+    if scene.lights.is_empty() || material.is_specular() {
+        return Colour::default();
+    }
+
+    let light = &scene.objects[scene.lights[rng.gen_range(0, scene.lights.len())]];
+    let light_rec = light.sample_record(rec, &Vec2::new(rng.gen(), rng.gen()));
+    let to_light = light_rec.point - rec.point;
+    let distance = to_light.mag();
+    if distance == 0.0 {
+        return Colour::default();
+    }
+    let wi = to_light / distance;
+
+    let frame = rec.shading_frame();
+    let wo = frame.to_local(&-ray.direction);
+    let wi_local = frame.to_local(&wi);
+    if !same_sides(rec, &-ray.direction, &wi, &wo, &wi_local) {
+        return Colour::default();
+    }
+    let f = material.f(&wo, &wi_local, rec) * abs_cos_theta(&wi_local);
+    let pdf = light.pdf_wi(rec, &wi) / scene.lights.len() as f32;
+    if f.is_black() || pdf == 0.0 {
+        return Colour::default();
+    }
+
+    let shadow_ray = Ray::new(rec.point, wi, ray.t_min, distance - ray.t_min);
+    if scene.intersect_predicate(&shadow_ray, true) {
+        return Colour::default();
+    }
+
+    let emitted = match scene.materials.get(light_rec.material_id) {
+        Some(light_material) => {
+            let light_wo = light_rec.shading_frame().to_local(&-wi);
+            light_material.emitted(&light_wo, &light_rec)
+        }
+        None => return Colour::error(),
+    };
+
+    let weight = power_heuristic(pdf, material.pdf(&wo, &wi_local, rec));
+
+    f * emitted * weight / pdf
+}
+
+// Combines light sampling and scattering with multiple importance sampling. `scattered_from` is
+// the previous hit and the pdf of scattering towards this one, or `None` for camera rays and
+// specular bounces, which light sampling can't find
+fn cast_ray(
+    ray: &Ray,
+    scene: &Scene,
+    depth: u32,
+    scattered_from: Option<(&IntersectRecord, f32)>,
+    rng: &mut StdRng,
+) -> Colour {
+    if depth == 0 {
+        return Colour::default();
+    }
+
+    let mut pixel_colour = Colour::default();
+
+    if let Some((mut rec, _, object)) = scene.intersect_object(ray, true) {
+        rec.compute_differentials(ray);
+        if let Some(material) = scene.materials.get(rec.material_id) {
+            material.perturb(&mut rec);
+            let wo = rec.shading_frame().to_local(&-ray.direction);
+            let emitted = material.emitted(&wo, &rec);
+            pixel_colour += match scattered_from {
+                Some((previous, scatter_pdf)) => {
+                    let light_pdf = scene.light_pdf(object, previous, &ray.direction.normalized());
+                    emitted * power_heuristic(scatter_pdf, light_pdf)
+                }
+                None => emitted,
+            };
+
+            // Light samples are paths one bounce longer, so they need the same depth to spare.
+            // They're taken whether or not scattering finds a direction, as the estimators are
+            // independent
+            if depth > 1 && !material.is_specular() {
+                pixel_colour += sample_light(ray, &rec, material, scene, rng);
+            }
+
+            if let Some((scattered, throughput, sample)) =
+                scatter_through(ray, &rec, object, material, scene, rng)
+            {
+                let scattered_from = if sample.specular {
+                    None
+                } else {
+                    Some((&rec, sample.pdf))
+                };
+                let incoming = cast_ray(&scattered, scene, depth - 1, scattered_from, rng);
+                pixel_colour += throughput * incoming;
+            }
+        } else {
+            pixel_colour = Colour::error();
+        }
+    } else {
+        // Light sampling never picks the background, so it always takes the full weight
+        pixel_colour = scene.background;
+    }
+
+    pixel_colour
+}
+
+// Only finds light by scattering into it
+fn cast_ray_reference(ray: &Ray, scene: &Scene, depth: u32, rng: &mut StdRng) -> Colour {
     if depth == 0 {
         return Colour::default();
     }
 
     let mut pixel_colour = Colour::default();
 
-    if let Some((rec, _)) = scene.intersect(&ray, true) {
+    if let Some((mut rec, _, object)) = scene.intersect_object(ray, true) {
+        rec.compute_differentials(ray);
         if let Some(material) = scene.materials.get(rec.material_id) {
-            let emitted = material.emitted(0.0, 0.0, &rec.point);
-            if let Some((scattered, colour)) = material.scatter(ray, &rec, rng) {
-                let pdf = material.pdf();
-                let cosine = (scattered.direction.dot(rec.normal)).max(0.0) / PI;
-                pixel_colour +=
-                    emitted + colour * cosine * cast_ray(&scattered, scene, depth - 1, rng) / pdf;
-            } else {
-                pixel_colour += emitted;
+            material.perturb(&mut rec);
+            let wo = rec.shading_frame().to_local(&-ray.direction);
+            pixel_colour += material.emitted(&wo, &rec);
+            if let Some((scattered, throughput, _)) =
+                scatter_through(ray, &rec, object, material, scene, rng)
+            {
+                pixel_colour += throughput * cast_ray_reference(&scattered, scene, depth - 1, rng);
             }
         } else {
             pixel_colour = Colour::error();
         }
+    } else {
+        pixel_colour = scene.background;
     }
-    // else {
-    //     pixel_colour = Colour::new(1.0, 1.0, 1.0);
-    // }
 
     pixel_colour
 }
 
 #[allow(dead_code)]
 fn furnace_test(aspect_ratio: f32) -> (Scene, Camera) {
-    let mut scene = Scene::default();
+    let mut scene = Scene {
+        background: Colour::new(1.0, 1.0, 1.0),
+        ..Default::default()
+    };
 
     let sphere_mat = scene.add_material(Box::new(Diffuse::new(Colour::new(0.18, 0.18, 0.18))));
 
@@ -111,7 +436,7 @@ fn furnace_test(aspect_ratio: f32) -> (Scene, Camera) {
         aperture,
         focus_distance,
         0.001,
-        std::f32::INFINITY,
+        f32::INFINITY,
     );
 
     (scene, camera)
@@ -173,7 +498,6 @@ fn scene_setup(aspect_ratio: f32) -> (Scene, Camera) {
     let otw = Mat4::from_translation(pos);
     let wto = Mat4::from_translation(-pos);
     let light = Sphere::from_transform(otw, wto, 0.5, light_mat, false);
-    scene.add_light_pos(pos);
     scene.add_object(Box::new(light));
     scene.generate_bvh();
 
@@ -193,7 +517,7 @@ fn scene_setup(aspect_ratio: f32) -> (Scene, Camera) {
         aperture,
         focus_distance,
         0.001,
-        std::f32::INFINITY,
+        f32::INFINITY,
     );
 
     (scene, camera)
@@ -212,81 +536,111 @@ impl Tile {
 }
 
 fn main() {
-    // TODO: Command line arguments
-    // Defaults
-    // const ASPECT_RATIO: f32 = 16.0 / 9.0;
-    const ASPECT_RATIO: f32 = 4.0 / 3.0;
-    const IMAGE_WIDTH: u32 = 480;
-    const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f32 / ASPECT_RATIO) as u32;
-    const SAMPLES: u32 = 1000;
-    const MAX_DEPTH: u32 = 10;
-    const STOP_DEPTH: u32 = MAX_DEPTH;
-    const DEBUG_NORMALS: bool = false;
-    const TILE_SIZE_X: u32 = 16;
-    const TILE_SIZE_Y: u32 = 16;
-    const TILES_X: u32 = (IMAGE_WIDTH + TILE_SIZE_X - 1) / TILE_SIZE_X;
-    const TILES_Y: u32 = (IMAGE_HEIGHT + TILE_SIZE_Y - 1) / TILE_SIZE_Y;
-    const TOTAL_TILES: u32 = TILES_X * TILES_Y;
+    let (settings, scene_source) = Settings::from_args();
+
+    if let Some(threads) = settings.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Couldn't configure the render thread pool");
+    }
 
     let time_date: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
     let time_start = std::time::Instant::now();
 
-    // TODO: Support parsing a file for scene setup
     // Setup scene and camera
-    let (scene, camera) = scene_setup(ASPECT_RATIO);
-    // let (scene, camera) = furnace_test(ASPECT_RATIO);
+    let (scene, camera) = match scene_source {
+        SceneSource::File(path, description) => match description.build(settings.aspect_ratio()) {
+            Ok(setup) => setup,
+            Err(err) => {
+                eprintln!("error: Invalid scene `{}`: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        SceneSource::Pbrt(pbrt) => pbrt.build(settings.aspect_ratio()),
+        SceneSource::Builtin(name) if name == "furnace" => furnace_test(settings.aspect_ratio()),
+        SceneSource::Builtin(_) => scene_setup(settings.aspect_ratio()),
+    };
 
     // Output image
-    let mut image = image::ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
+    let image_width = settings.image_width;
+    let image_height = settings.image_height;
+    let mut image = image::ImageBuffer::new(image_width, image_height);
+
+    // Pixel steps across the viewport, which a single row or column of pixels has none of
+    let width_steps = (image_width - 1).max(1) as f64;
+    let height_steps = (image_height - 1).max(1) as f64;
+
+    // Differentials step one pixel across the viewport, shrunk as samples share the pixel
+    let du = 1.0 / width_steps;
+    let dv = -1.0 / height_steps;
+    let differential_scale = (1.0 / (settings.samples as f32).sqrt()).max(0.125);
 
     // Render scene
-    let tiles = crossbeam::queue::ArrayQueue::new((TILES_X * TILES_Y) as usize);
-    (0..IMAGE_HEIGHT)
-        .into_par_iter()
-        .step_by(TILE_SIZE_Y as usize)
-        .for_each(|y| {
-            let mut rng = rand::thread_rng();
-            for x in (0..IMAGE_WIDTH).step_by(TILE_SIZE_X as usize) {
-                // Current tile to render
-                let mut tile = Tile::new(
-                    x as u32,
-                    y as u32,
-                    image::RgbImage::new(TILE_SIZE_X, TILE_SIZE_Y),
-                );
-
-                // Core render loop
-                for (tx, ty, pixel) in tile.data.enumerate_pixels_mut() {
-                    let mut pixel_colour = Colour::default();
-
-                    // TODO: Unroll and use SIMD vectors
-                    // Jitter rays around
-                    for _ in 0..SAMPLES {
-                        let u = ((x + tx) as f64 + rng.gen::<f64>()) / (IMAGE_WIDTH - 1) as f64;
-                        let v =
-                            1.0 - ((y + ty) as f64 + rng.gen::<f64>()) / (IMAGE_HEIGHT - 1) as f64;
-                        let ray = camera.get_ray(u, v, &mut rng);
-
-                        if DEBUG_NORMALS {
-                            pixel_colour =
-                                debug_normals(&ray, &scene, MAX_DEPTH, STOP_DEPTH, &mut rng);
-                        } else {
+    let tiles = crossbeam::queue::ArrayQueue::new(settings.total_tiles() as usize);
+    (0..settings.tiles_y()).into_par_iter().for_each(|tile_y| {
+        let y = tile_y * settings.tile_height;
+        for tile_x in 0..settings.tiles_x() {
+            let x = tile_x * settings.tile_width;
+            let mut rng = settings.tile_rng(tile_x, tile_y);
+
+            // Current tile to render
+            let mut tile = Tile::new(
+                x,
+                y,
+                image::RgbImage::new(settings.tile_width, settings.tile_height),
+            );
+
+            // Core render loop
+            for (tx, ty, pixel) in tile.data.enumerate_pixels_mut() {
+                let mut pixel_colour = Colour::default();
+
+                // TODO: Unroll and use SIMD vectors
+                // Jitter rays around
+                for _ in 0..settings.samples {
+                    let u = ((x + tx) as f64 + rng.gen::<f64>()) / width_steps;
+                    let v = 1.0 - ((y + ty) as f64 + rng.gen::<f64>()) / height_steps;
+                    let mut ray = camera.get_ray_differential(u, v, du, dv, &mut rng);
+                    ray.scale_differential(differential_scale);
+
+                    match settings.integrator {
+                        Integrator::Normals => {
+                            pixel_colour = debug_normals(
+                                &ray,
+                                &scene,
+                                settings.max_depth,
+                                settings.max_depth,
+                                &mut rng,
+                            );
+                        }
+                        Integrator::PathTracer => {
                             pixel_colour +=
-                                cast_ray(&ray, &scene, MAX_DEPTH, &mut rng) / SAMPLES as f64;
+                                cast_ray(&ray, &scene, settings.max_depth, None, &mut rng)
+                                    / settings.samples as f64;
+                        }
+                        Integrator::Reference => {
+                            pixel_colour +=
+                                cast_ray_reference(&ray, &scene, settings.max_depth, &mut rng)
+                                    / settings.samples as f64;
                         }
                     }
-
-                    // Output pixel colour
-                    pixel_colour.gamma_correct_mut();
-                    *pixel = image::Rgb(pixel_colour.to_u8());
                 }
 
-                tiles.push(tile).unwrap();
-
-                // TODO: Don't flood output
-                // Update user on progress
-                println!("{:>6.2}", tiles.len() as f64 / TOTAL_TILES as f64 * 100.0)
+                // Output pixel colour
+                pixel_colour.gamma_correct_mut();
+                *pixel = image::Rgb(pixel_colour.to_u8());
             }
-        });
+
+            tiles.push(tile).unwrap();
+
+            // TODO: Don't flood output
+            // Update user on progress
+            println!(
+                "{:>6.2}",
+                tiles.len() as f64 / settings.total_tiles() as f64 * 100.0
+            )
+        }
+    });
 
     // Draw tiles to output image
     while !tiles.is_empty() {
@@ -296,8 +650,8 @@ fn main() {
 
     // Save
     image
-        .save("output.png")
-        .expect("Couldn't save `output.png`");
+        .save(&settings.output)
+        .unwrap_or_else(|_| panic!("Couldn't save `{}`", settings.output.display()));
 
     println!("Complete.");
 
@@ -317,16 +671,100 @@ fn main() {
         &mut results,
         "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
         time_date.to_rfc3339(),
-        IMAGE_WIDTH,
-        IMAGE_HEIGHT,
-        TILES_X,
-        TILE_SIZE_X,
-        TILES_Y,
-        TILE_SIZE_Y,
-        SAMPLES,
-        MAX_DEPTH,
-        DEBUG_NORMALS,
+        image_width,
+        image_height,
+        settings.tiles_x(),
+        settings.tile_width,
+        settings.tiles_y(),
+        settings.tile_height,
+        settings.samples,
+        settings.max_depth,
+        settings.integrator == Integrator::Normals,
         total_seconds
     )
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    // A diffuse sphere on the ground, lit by a light just out of view so that the pixels only
+    // see reflected light
+    fn lit_sphere() -> (Scene, Camera) {
+        let mut scene = Scene::default();
+        let ground_mat = scene.add_material(Box::new(Diffuse::new(Colour::new(0.7, 0.7, 0.7))));
+        let sphere_mat = scene.add_material(Box::new(Diffuse::new(Colour::new(0.8, 0.4, 0.2))));
+        let light_mat =
+            scene.add_material(Box::new(Emissive::new(Colour::new(1.0, 1.0, 1.0), 1.0)));
+
+        let ground = Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, ground_mat, false);
+        scene.add_object(Box::new(ground));
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, sphere_mat, false);
+        scene.add_object(Box::new(sphere));
+        let light = Sphere::new(Vec3::new(0.0, 6.0, 0.0), 3.0, light_mat, false);
+        scene.add_object(Box::new(light));
+        scene.generate_bvh();
+
+        let camera = Camera::new(
+            Vec3::new(0.0, 1.0, -5.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            10.0,
+            0.001,
+            f32::INFINITY,
+        );
+
+        (scene, camera)
+    }
+
+    // Mean radiance over a small image of `lit_sphere`
+    fn mean_radiance(integrator: Integrator, samples: u32) -> Colour {
+        const SIZE: u32 = 16;
+        const MAX_DEPTH: u32 = 5;
+        let (scene, camera) = lit_sphere();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let mut total = Colour::default();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                for _ in 0..samples {
+                    let u = (x as f64 + rng.gen::<f64>()) / SIZE as f64;
+                    let v = 1.0 - (y as f64 + rng.gen::<f64>()) / SIZE as f64;
+                    let ray = camera.get_ray(u, v, &mut rng);
+                    total += match integrator {
+                        Integrator::Reference => {
+                            cast_ray_reference(&ray, &scene, MAX_DEPTH, &mut rng)
+                        }
+                        _ => cast_ray(&ray, &scene, MAX_DEPTH, None, &mut rng),
+                    };
+                }
+            }
+        }
+
+        total / (SIZE * SIZE * samples) as f64
+    }
+
+    #[test]
+    fn path_tracer_matches_reference() {
+        let path = mean_radiance(Integrator::PathTracer, 32);
+        let reference = mean_radiance(Integrator::Reference, 512);
+
+        for (a, b) in [
+            (path.r, reference.r),
+            (path.g, reference.g),
+            (path.b, reference.b),
+        ] {
+            assert!(
+                (a - b).abs() <= 0.05 * b,
+                "path tracer gave {:?}, reference gave {:?}",
+                path,
+                reference
+            );
+        }
+    }
+}
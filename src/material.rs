@@ -1,17 +1,25 @@
+use crate::bsdf::{
+    abs_cos_theta, cos_theta, diffuse_fresnel_reflectance, fresnel_dielectric, reflect, refract,
+    same_hemisphere, sin_theta, BSDFSample, Fresnel,
+};
+use crate::bump::NormalModifier;
 use crate::colour::Colour;
 use crate::intersectable::IntersectRecord;
-use crate::ray::Ray;
-use crate::utils::{create_coordinates_system, uniform_sample_hemisphere};
-use rand::prelude::ThreadRng;
+use crate::microfacet::TrowbridgeReitz;
+use crate::texture::{ConstantTexture, Texture};
+use crate::utils::{cosine_sample_hemisphere, mix_bits};
+use rand::rngs::StdRng;
 use rand::Rng;
 use std::f32::consts::PI;
-use ultraviolet::{Mat3, Vec3};
+use std::sync::Arc;
+use ultraviolet::{Vec2, Vec3};
 
 #[derive(Default, Copy, Clone, Debug)]
 pub struct MaterialID(usize);
 
+// Materials are shared so mixes can hold on to the materials they blend
 #[derive(Default)]
-pub struct MaterialStore(Vec<Box<dyn Material>>);
+pub struct MaterialStore(Vec<Arc<dyn Material>>);
 
 #[allow(dead_code)]
 impl MaterialStore {
@@ -20,81 +28,1329 @@ impl MaterialStore {
     }
 
     pub fn add(&mut self, material: Box<dyn Material>) -> MaterialID {
-        self.0.push(material);
+        self.0.push(Arc::from(material));
 
         MaterialID(self.0.len() - 1)
     }
 
-    pub fn get(&self, material_id: MaterialID) -> Option<&Box<dyn Material>> {
-        self.0.get(material_id.0)
+    // Blends two materials already in the store, or returns `None` if either doesn't exist. As a
+    // mix can only reference materials added before it, it can never reference itself
+    pub fn add_mix(
+        &mut self,
+        first: MaterialID,
+        second: MaterialID,
+        amount: f32,
+    ) -> Option<MaterialID> {
+        self.add_textured_mix(first, second, Arc::new(ConstantTexture::scalar(amount)))
+    }
+
+    // Like `add_mix`, with the amount varying over the surface
+    pub fn add_textured_mix(
+        &mut self,
+        first: MaterialID,
+        second: MaterialID,
+        amount: Arc<dyn Texture>,
+    ) -> Option<MaterialID> {
+        let mix = Mix {
+            materials: [self.0.get(first.0)?.clone(), self.0.get(second.0)?.clone()],
+            amount,
+        };
+
+        Some(self.add(Box::new(mix)))
+    }
+
+    // Shades each side of a surface as its front with a material already in the store, or returns
+    // `None` if either doesn't exist
+    pub fn add_two_sided(&mut self, front: MaterialID, back: MaterialID) -> Option<MaterialID> {
+        let two_sided = TwoSided {
+            front: self.0.get(front.0)?.clone(),
+            back: self.0.get(back.0)?.clone(),
+        };
+
+        Some(self.add(Box::new(two_sided)))
+    }
+
+    // Shades a material already in the store with its shading normals moved by `modifier`, or
+    // returns `None` if it doesn't exist
+    pub fn add_perturbed(
+        &mut self,
+        material: MaterialID,
+        modifier: NormalModifier,
+    ) -> Option<MaterialID> {
+        let perturbed = Perturbed {
+            material: self.0.get(material.0)?.clone(),
+            modifier,
+        };
+
+        Some(self.add(Box::new(perturbed)))
+    }
+
+    pub fn get(&self, material_id: MaterialID) -> Option<&dyn Material> {
+        self.0.get(material_id.0).map(|material| material.as_ref())
     }
 }
 
-// TODO: Add more materials
-// TODO: Use true BSDFs
+// Directions are in the local shading frame around the surface normal, see `bsdf::Frame`
 pub trait Material: Send + Sync {
-    fn scatter(
+    // BSDF value for light arriving from `wi` and leaving along `wo`
+    fn f(&self, _wo: &Vec3, _wi: &Vec3, _rec: &IntersectRecord) -> Colour {
+        Colour::default()
+    }
+
+    // Samples an incoming direction for light leaving along `wo`
+    fn sample_f(
         &self,
-        _ray: &Ray,
+        _wo: &Vec3,
         _rec: &IntersectRecord,
-        _rng: &mut ThreadRng,
-    ) -> Option<(Ray, Colour)> {
+        _rng: &mut StdRng,
+    ) -> Option<BSDFSample> {
         None
     }
 
-    #[inline]
-    fn pdf(&self) -> f32 {
-        0.5 / PI
+    // Probability of `sample_f` choosing `wi` for light leaving along `wo`
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &IntersectRecord) -> f32 {
+        0.0
     }
 
-    fn emitted(&self, _u: f32, _v: f32, _point: &Vec3) -> Colour {
+    // Whether every lobe is a delta distribution, so light sampling can't find anything
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // Radiance emitted along `wo`
+    fn emitted(&self, _wo: &Vec3, _rec: &IntersectRecord) -> Colour {
         Colour::default()
     }
+
+    // Medium filling the inside of the shape, which light refracted into it at `rec` scatters
+    // through
+    fn interior(&self, _rec: &IntersectRecord) -> Option<Medium> {
+        None
+    }
+
+    // Moves the shading normal of a hit before it's shaded. Mixes can't pass this or `interior`
+    // on, as they choose a material per direction, so scene files don't let them hold materials
+    // that use either
+    fn perturb(&self, _rec: &mut IntersectRecord) {}
+}
+
+// Homogeneous medium with scattering and extinction coefficients per unit of scene distance
+#[derive(Copy, Clone, Debug)]
+pub struct Medium {
+    pub sigma_s: Colour,
+    pub sigma_t: Colour,
+}
+
+impl Medium {
+    // From the albedo of each scattering event and the average distance between interactions
+    pub fn new(albedo: Colour, mean_free_path: Colour) -> Medium {
+        let sigma_t = Colour::new(
+            1.0 / mean_free_path.r,
+            1.0 / mean_free_path.g,
+            1.0 / mean_free_path.b,
+        );
+
+        Medium {
+            sigma_s: albedo * sigma_t,
+            sigma_t,
+        }
+    }
 }
 
 pub struct Diffuse {
-    pub albedo: Colour,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Diffuse {
     pub fn new(albedo: Colour) -> Diffuse {
+        Diffuse::textured(Arc::new(ConstantTexture::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Diffuse {
         Diffuse { albedo }
     }
 }
 
 impl Material for Diffuse {
-    fn scatter(
-        &self,
-        ray: &Ray,
-        rec: &IntersectRecord,
-        rng: &mut ThreadRng,
-    ) -> Option<(Ray, Colour)> {
-        let r1 = rng.gen::<f32>();
-        let r2 = rng.gen::<f32>();
-        let w = rec.normal;
-        let (u, v) = create_coordinates_system(&w);
-        let scattered_local = uniform_sample_hemisphere(r1, r2);
-        let scattered_dir = scattered_local.x * u + scattered_local.y * v + scattered_local.z * w;
-        let scattered = Ray::new(rec.point, scattered_dir, ray.t_min, ray.t_max);
-        let colour = self.albedo;
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        if same_hemisphere(wo, wi) {
+            self.albedo.evaluate(rec) / PI
+        } else {
+            Colour::default()
+        }
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        sample_cosine_weighted(self, wo, rec, rng)
+    }
 
-        Some((scattered, colour))
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> f32 {
+        cosine_weighted_pdf(wo, wi)
+    }
+}
+
+// Rough diffuse reflection from V-shaped microfacets with a Gaussian distribution of slopes, where
+// `sigma` is the standard deviation of the facet angle in degrees. The same as `Diffuse` when
+// `sigma` is zero
+pub struct OrenNayar {
+    pub albedo: Arc<dyn Texture>,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Colour, sigma: f32) -> OrenNayar {
+        OrenNayar::textured(Arc::new(ConstantTexture::new(albedo)), sigma)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, sigma: f32) -> OrenNayar {
+        let sigma2 = sigma.to_radians().powi(2);
+
+        OrenNayar {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Material for OrenNayar {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        if !same_hemisphere(wo, wi) {
+            return Colour::default();
+        }
+
+        let sin_theta_i = sin_theta(wi);
+        let sin_theta_o = sin_theta(wo);
+        // Cosine of the azimuthal angle between the directions, if both have one
+        let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_theta_i * sin_theta_o)).max(0.0)
+        } else {
+            0.0
+        };
+
+        let (sin_alpha, tan_beta) = if abs_cos_theta(wi) > abs_cos_theta(wo) {
+            (sin_theta_o, sin_theta_i / abs_cos_theta(wi))
+        } else {
+            (sin_theta_i, sin_theta_o / abs_cos_theta(wo))
+        };
+
+        self.albedo.evaluate(rec) / PI * (self.a + self.b * max_cos * sin_alpha * tan_beta)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        sample_cosine_weighted(self, wo, rec, rng)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> f32 {
+        cosine_weighted_pdf(wo, wi)
+    }
+}
+
+// Blends two materials by `amount`, the fraction of the second. Each shading point stochastically
+// uses one of them, chosen by hashing the point and outgoing direction so every query at the same
+// hit agrees
+pub struct Mix {
+    materials: [Arc<dyn Material>; 2],
+    amount: Arc<dyn Texture>,
+}
+
+impl Mix {
+    fn choose(&self, rec: &IntersectRecord, wo: &Vec3) -> &dyn Material {
+        let point = rec.point;
+        let mut hash = 0u64;
+        for value in [point.x, point.y, point.z, wo.x, wo.y, wo.z] {
+            hash = mix_bits(hash ^ value.to_bits() as u64);
+        }
+        // Top 24 bits as a uniform float in [0, 1)
+        let u = (hash >> 40) as f64 / (1u64 << 24) as f64;
+
+        if u < self.amount.evaluate_scalar(rec) {
+            self.materials[1].as_ref()
+        } else {
+            self.materials[0].as_ref()
+        }
+    }
+}
+
+impl Material for Mix {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.choose(rec, wo).f(wo, wi, rec)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        self.choose(rec, wo).sample_f(wo, rec, rng)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        self.choose(rec, wo).pdf(wo, wi, rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.materials.iter().all(|material| material.is_specular())
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.materials.iter().any(|material| material.is_emissive())
+    }
+
+    fn emitted(&self, wo: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.choose(rec, wo).emitted(wo, rec)
+    }
+}
+
+// Samples a cosine-weighted direction for diffuse materials, reflecting on whichever side the ray
+// arrived from
+pub fn sample_cosine_weighted<M: Material>(
+    material: &M,
+    wo: &Vec3,
+    rec: &IntersectRecord,
+    rng: &mut StdRng,
+) -> Option<BSDFSample> {
+    let mut wi = cosine_sample_hemisphere(&Vec2::new(rng.gen(), rng.gen()));
+    if wo.z < 0.0 {
+        wi.z = -wi.z;
+    }
+    let pdf = cosine_weighted_pdf(wo, &wi);
+    if pdf == 0.0 {
+        return None;
+    }
+
+    Some(BSDFSample {
+        wi,
+        f: material.f(wo, &wi, rec),
+        pdf,
+        specular: false,
+    })
+}
+
+pub fn cosine_weighted_pdf(wo: &Vec3, wi: &Vec3) -> f32 {
+    if same_hemisphere(wo, wi) {
+        abs_cos_theta(wi) / PI
+    } else {
+        0.0
     }
 }
 
 pub struct Emissive {
-    pub albedo: Colour,
+    pub albedo: Arc<dyn Texture>,
     pub intensity: f32,
 }
 
 impl Emissive {
     pub fn new(albedo: Colour, intensity: f32) -> Emissive {
+        Emissive::textured(Arc::new(ConstantTexture::new(albedo)), intensity)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, intensity: f32) -> Emissive {
         Emissive { albedo, intensity }
     }
 }
 
 impl Material for Emissive {
-    fn emitted(&self, _u: f32, _v: f32, _point: &Vec3) -> Colour {
-        self.albedo * self.intensity
+    fn emitted(&self, _wo: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.albedo.evaluate(rec) * self.intensity
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+// Measured complex indices of refraction (`eta`, `k`) at the red, green and blue wavelengths
+pub const CONDUCTOR_PRESETS: [(&str, [f64; 3], [f64; 3]); 5] = [
+    (
+        "gold",
+        [0.143119, 0.374957, 1.44248],
+        [3.98316, 2.38572, 1.60322],
+    ),
+    (
+        "copper",
+        [0.200438, 0.924033, 1.10221],
+        [3.91295, 2.45285, 2.14219],
+    ),
+    (
+        "aluminium",
+        [1.65746, 0.880369, 0.521229],
+        [9.22387, 6.26952, 4.837],
+    ),
+    (
+        "silver",
+        [0.155265, 0.116723, 0.138342],
+        [4.82835, 3.12225, 2.14696],
+    ),
+    ("iron", [2.9114, 2.9497, 2.5845], [3.0893, 2.9318, 2.767]),
+];
+
+// Conductor that reflects with a tinted Schlick Fresnel or the full conductor Fresnel equations,
+// a perfect mirror when smooth and a GGX microfacet lobe otherwise
+pub struct Metal {
+    reflectance: Reflectance,
+    roughness: Roughness,
+}
+
+// Fresnel term of a metal, fixed or with a Schlick tint that varies over the surface
+enum Reflectance {
+    Fresnel(Fresnel),
+    Textured(Arc<dyn Texture>),
+}
+
+impl Reflectance {
+    #[inline]
+    fn at(&self, rec: &IntersectRecord) -> Fresnel {
+        match self {
+            Reflectance::Fresnel(fresnel) => *fresnel,
+            Reflectance::Textured(albedo) => Fresnel::Schlick(albedo.evaluate(rec)),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Metal {
+    pub fn new(albedo: Colour, roughness: f32) -> Metal {
+        Metal::anisotropic(albedo, roughness, roughness)
+    }
+
+    // Roughness along the two tangent directions of the shading frame
+    pub fn anisotropic(albedo: Colour, roughness_u: f32, roughness_v: f32) -> Metal {
+        Metal {
+            reflectance: Reflectance::Fresnel(Fresnel::Schlick(albedo)),
+            roughness: Roughness::new(roughness_u, roughness_v),
+        }
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, roughness: Roughness) -> Metal {
+        Metal {
+            reflectance: Reflectance::Textured(albedo),
+            roughness,
+        }
+    }
+
+    // Physically based conductor with complex index of refraction `eta + ik`
+    pub fn conductor(eta: Colour, k: Colour, roughness_u: f32, roughness_v: f32) -> Metal {
+        Metal::textured_conductor(eta, k, Roughness::new(roughness_u, roughness_v))
+    }
+
+    pub fn textured_conductor(eta: Colour, k: Colour, roughness: Roughness) -> Metal {
+        Metal {
+            reflectance: Reflectance::Fresnel(Fresnel::Conductor { eta, k }),
+            roughness,
+        }
+    }
+
+    // Conductor with one of the `CONDUCTOR_PRESETS`
+    pub fn preset(name: &str, roughness_u: f32, roughness_v: f32) -> Option<Metal> {
+        let (eta, k) = conductor_preset(name)?;
+        Some(Metal::conductor(eta, k, roughness_u, roughness_v))
+    }
+}
+
+// Complex index of refraction of a preset conductor, by name
+pub fn conductor_preset(name: &str) -> Option<(Colour, Colour)> {
+    CONDUCTOR_PRESETS
+        .iter()
+        .find(|(preset, _, _)| *preset == name)
+        .map(|(_, eta, k)| {
+            (
+                Colour::new(eta[0], eta[1], eta[2]),
+                Colour::new(k[0], k[1], k[2]),
+            )
+        })
+}
+
+impl Material for Metal {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) if same_hemisphere(wo, wi) => distribution,
+            _ => return Colour::default(),
+        };
+
+        let cos_theta_o = abs_cos_theta(wo);
+        let cos_theta_i = abs_cos_theta(wi);
+        let wh = *wo + *wi;
+        if cos_theta_o == 0.0 || cos_theta_i == 0.0 || wh.mag_sq() == 0.0 {
+            return Colour::default();
+        }
+        let wh = wh.normalized();
+
+        let fresnel = self.reflectance.at(rec).evaluate(wi.dot(wh));
+        fresnel * distribution.d(&wh) * distribution.g(wo, wi) / (4.0 * cos_theta_o * cos_theta_i)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) => distribution,
+            None => {
+                let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                return Some(BSDFSample {
+                    wi,
+                    f: self.reflectance.at(rec).evaluate(wi.z) / abs_cos_theta(&wi),
+                    pdf: 1.0,
+                    specular: true,
+                });
+            }
+        };
+
+        let wh = distribution.sample_wh(wo, &Vec2::new(rng.gen(), rng.gen()));
+        let wi = reflect(wo, &wh);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi,
+            f: self.f(wo, &wi, rec),
+            pdf: self.pdf(wo, &wi, rec),
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) if same_hemisphere(wo, wi) => distribution,
+            _ => return 0.0,
+        };
+
+        let wh = *wo + *wi;
+        if wh.mag_sq() == 0.0 {
+            return 0.0;
+        }
+        let wh = wh.normalized();
+
+        distribution.pdf(wo, &wh) / (4.0 * wo.dot(wh).abs())
+    }
+
+    fn is_specular(&self) -> bool {
+        self.roughness.is_smooth()
+    }
+}
+
+// Glass-like interface that reflects or refracts in proportion to the Fresnel reflectance, either
+// smooth or with a GGX microfacet distribution
+pub struct Dielectric {
+    pub ior: f32,
+    roughness: Roughness,
+}
+
+#[allow(dead_code)]
+impl Dielectric {
+    pub fn new(ior: f32) -> Dielectric {
+        Dielectric::rough(ior, 0.0, 0.0)
+    }
+
+    pub fn rough(ior: f32, roughness_u: f32, roughness_v: f32) -> Dielectric {
+        Dielectric::textured(ior, Roughness::new(roughness_u, roughness_v))
+    }
+
+    pub fn textured(ior: f32, roughness: Roughness) -> Dielectric {
+        Dielectric { ior, roughness }
+    }
+
+    // Rays leaving the shape go from the inside medium back to the outside
+    #[inline]
+    fn eta(&self, rec: &IntersectRecord) -> f32 {
+        if rec.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+
+    fn sample_smooth(
+        &self,
+        wo: &Vec3,
+        rec: &IntersectRecord,
+        rng: &mut StdRng,
+    ) -> Option<BSDFSample> {
+        let eta = self.eta(rec);
+        let reflectance = fresnel_dielectric(abs_cos_theta(wo), eta);
+
+        if rng.gen::<f32>() < reflectance {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            return Some(BSDFSample {
+                wi,
+                f: Colour::new(1.0, 1.0, 1.0) * reflectance / abs_cos_theta(&wi),
+                pdf: reflectance,
+                specular: true,
+            });
+        }
+
+        let n = Vec3::new(0.0, 0.0, wo.z.signum());
+        let wi = refract(wo, &n, eta)?;
+        // Radiance is compressed into a smaller solid angle when entering a denser medium
+        let transmittance = (1.0 - reflectance) / eta.powi(2);
+
+        Some(BSDFSample {
+            wi,
+            f: Colour::new(1.0, 1.0, 1.0) * transmittance / abs_cos_theta(&wi),
+            pdf: 1.0 - reflectance,
+            specular: true,
+        })
+    }
+
+    // Microfacet normal for a pair of directions, facing out of the surface. `eta` is the relative
+    // index of refraction for transmission, or 1 for reflection
+    fn half_vector(wo: &Vec3, wi: &Vec3, eta: f32) -> Option<Vec3> {
+        let wh = *wi * eta + *wo;
+        if wh.mag_sq() == 0.0 {
+            return None;
+        }
+        let wh = wh.normalized();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+
+        // Discard microfacets that face away from either direction
+        if wh.dot(*wi) * wi.z < 0.0 || wh.dot(*wo) * wo.z < 0.0 {
+            None
+        } else {
+            Some(wh)
+        }
+    }
+}
+
+impl Material for Dielectric {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) => distribution,
+            None => return Colour::default(),
+        };
+        let cos_theta_o = cos_theta(wo);
+        let cos_theta_i = cos_theta(wi);
+        if cos_theta_o == 0.0 || cos_theta_i == 0.0 {
+            return Colour::default();
+        }
+
+        let is_reflection = same_hemisphere(wo, wi);
+        let eta = if is_reflection { 1.0 } else { self.eta(rec) };
+        let wh = match Dielectric::half_vector(wo, wi, eta) {
+            Some(wh) => wh,
+            None => return Colour::default(),
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(wh).abs(), self.eta(rec));
+        let d_g = distribution.d(&wh) * distribution.g(wo, wi);
+        let f = if is_reflection {
+            d_g * reflectance / (4.0 * cos_theta_i * cos_theta_o).abs()
+        } else {
+            let denominator = (wi.dot(wh) + wo.dot(wh) / eta).powi(2) * cos_theta_i * cos_theta_o;
+            // Scaled for the compression of radiance as with smooth refraction
+            d_g * (1.0 - reflectance) * (wi.dot(wh) * wo.dot(wh) / denominator).abs() / eta.powi(2)
+        };
+
+        Colour::new(1.0, 1.0, 1.0) * f
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) => distribution,
+            None => return self.sample_smooth(wo, rec, rng),
+        };
+
+        let wh = distribution.sample_wh(wo, &Vec2::new(rng.gen(), rng.gen()));
+        let reflectance = fresnel_dielectric(wo.dot(wh).abs(), self.eta(rec));
+        let wi = if rng.gen::<f32>() < reflectance {
+            let wi = reflect(wo, &wh);
+            if !same_hemisphere(wo, &wi) {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(wo, &wh, self.eta(rec))?;
+            if same_hemisphere(wo, &wi) || wi.z == 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi, rec);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi,
+            f: self.f(wo, &wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        let distribution = match self.roughness.distribution(rec) {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+
+        let is_reflection = same_hemisphere(wo, wi);
+        let eta = if is_reflection { 1.0 } else { self.eta(rec) };
+        let wh = match Dielectric::half_vector(wo, wi, eta) {
+            Some(wh) => wh,
+            None => return 0.0,
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(wh).abs(), self.eta(rec));
+        if is_reflection {
+            distribution.pdf(wo, &wh) / (4.0 * wo.dot(wh).abs()) * reflectance
+        } else {
+            let denominator = (wi.dot(wh) + wo.dot(wh) / eta).powi(2);
+            let dwh_dwi = wi.dot(wh).abs() / denominator;
+            distribution.pdf(wo, &wh) * dwh_dwi * (1.0 - reflectance)
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        self.roughness.is_smooth()
+    }
+}
+
+// Diffuse base under a dielectric coating, like plastic or varnished wood. Light refracts into the
+// coating, bounces between the base and the inside of the interface until it escapes, and some is
+// reflected by the interface itself, which is smooth or a GGX microfacet surface
+pub struct Plastic {
+    albedo: Arc<dyn Texture>,
+    pub ior: f32,
+    roughness: Roughness,
+    // Fraction of the diffusely scattered light reflected back down by the inside of the coating
+    internal_reflectance: f32,
+}
+
+#[allow(dead_code)]
+impl Plastic {
+    pub fn new(albedo: Colour, ior: f32, roughness: f32) -> Plastic {
+        Plastic::textured(
+            Arc::new(ConstantTexture::new(albedo)),
+            ior,
+            Roughness::new(roughness, roughness),
+        )
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, ior: f32, roughness: Roughness) -> Plastic {
+        Plastic {
+            albedo,
+            ior,
+            roughness,
+            internal_reflectance: diffuse_fresnel_reflectance(1.0 / ior),
+        }
+    }
+
+    // More than all the light can't survive a bounce off the base, or the internal bounces would
+    // never die out
+    #[inline]
+    fn albedo(&self, rec: &IntersectRecord) -> Colour {
+        self.albedo.evaluate(rec).clamp(0.0, 1.0)
+    }
+
+    // Chance of sampling the interface rather than the base for light leaving at `cos_theta_o`.
+    // The interface is sampled more often over darker bases, which scatter less
+    fn specular_probability(&self, cos_theta_o: f32, albedo: &Colour) -> f32 {
+        let specular_weight = 1.0 / (1.0 + albedo.luminance() as f32);
+        let fresnel = fresnel_dielectric(cos_theta_o, self.ior);
+        let specular = fresnel * specular_weight;
+
+        specular / (specular + (1.0 - fresnel) * (1.0 - specular_weight))
+    }
+}
+
+impl Material for Plastic {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        if !same_hemisphere(wo, wi) {
+            return Colour::default();
+        }
+        let cos_theta_o = abs_cos_theta(wo);
+        let cos_theta_i = abs_cos_theta(wi);
+
+        // The geometric series of internal bounces sums to 1 / (1 - albedo * internal_reflectance)
+        let transmittance = (1.0 - fresnel_dielectric(cos_theta_i, self.ior))
+            * (1.0 - fresnel_dielectric(cos_theta_o, self.ior));
+        let albedo = self.albedo(rec);
+        let base = Colour::new(
+            albedo.r / (1.0 - albedo.r * self.internal_reflectance as f64),
+            albedo.g / (1.0 - albedo.g * self.internal_reflectance as f64),
+            albedo.b / (1.0 - albedo.b * self.internal_reflectance as f64),
+        );
+        let mut f = base * (transmittance / (PI * self.ior.powi(2)));
+
+        if let Some(distribution) = self.roughness.distribution(rec) {
+            let wh = *wo + *wi;
+            if cos_theta_o > 0.0 && cos_theta_i > 0.0 && wh.mag_sq() > 0.0 {
+                let wh = wh.normalized();
+                let fresnel = fresnel_dielectric(wo.dot(wh).abs(), self.ior);
+                f += Colour::new(1.0, 1.0, 1.0)
+                    * (fresnel * distribution.d(&wh) * distribution.g(wo, wi)
+                        / (4.0 * cos_theta_o * cos_theta_i));
+            }
+        }
+
+        f
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let specular_probability = self.specular_probability(abs_cos_theta(wo), &self.albedo(rec));
+
+        let wi = if rng.gen::<f32>() < specular_probability {
+            match self.roughness.distribution(rec) {
+                Some(distribution) => {
+                    let wh = distribution.sample_wh(wo, &Vec2::new(rng.gen(), rng.gen()));
+                    reflect(wo, &wh)
+                }
+                None => {
+                    let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                    let fresnel = fresnel_dielectric(abs_cos_theta(wo), self.ior);
+                    return Some(BSDFSample {
+                        wi,
+                        f: Colour::new(1.0, 1.0, 1.0) * fresnel / abs_cos_theta(&wi),
+                        pdf: specular_probability,
+                        specular: true,
+                    });
+                }
+            }
+        } else {
+            let mut wi = cosine_sample_hemisphere(&Vec2::new(rng.gen(), rng.gen()));
+            if wo.z < 0.0 {
+                wi.z = -wi.z;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi, rec);
+        if !same_hemisphere(wo, &wi) || pdf == 0.0 {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi,
+            f: self.f(wo, &wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let specular_probability = self.specular_probability(abs_cos_theta(wo), &self.albedo(rec));
+        let mut pdf = (1.0 - specular_probability) * cosine_weighted_pdf(wo, wi);
+
+        if let Some(distribution) = self.roughness.distribution(rec) {
+            let wh = *wo + *wi;
+            if wh.mag_sq() > 0.0 {
+                let wh = wh.normalized();
+                pdf += specular_probability * distribution.pdf(wo, &wh) / (4.0 * wo.dot(wh).abs());
+            }
+        }
+
+        pdf
+    }
+}
+
+// Translucent material like skin, marble or wax. Light refracts through a smooth dielectric
+// boundary and random walks through the medium inside until it's absorbed or leaves the shape
+pub struct Subsurface {
+    boundary: Dielectric,
+    albedo: Arc<dyn Texture>,
+    mean_free_path: Colour,
+}
+
+#[allow(dead_code)]
+impl Subsurface {
+    pub fn new(albedo: Colour, mean_free_path: Colour, ior: f32) -> Subsurface {
+        Subsurface::textured(Arc::new(ConstantTexture::new(albedo)), mean_free_path, ior)
+    }
+
+    // The medium is homogeneous, taking the albedo from where the light entered it
+    pub fn textured(albedo: Arc<dyn Texture>, mean_free_path: Colour, ior: f32) -> Subsurface {
+        Subsurface {
+            boundary: Dielectric::new(ior),
+            albedo,
+            mean_free_path,
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        self.boundary.sample_f(wo, rec, rng)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn interior(&self, rec: &IntersectRecord) -> Option<Medium> {
+        let albedo = self.albedo.evaluate(rec).clamp(0.0, 1.0);
+        Some(Medium::new(albedo, self.mean_free_path))
+    }
+}
+
+// Dielectric slab too thin to offset or bend the light passing through it, like a window pane or a
+// soap bubble. Light bouncing between the two faces is summed into the reflection and transmission
+pub struct ThinDielectric {
+    pub ior: f32,
+}
+
+impl ThinDielectric {
+    pub fn new(ior: f32) -> ThinDielectric {
+        ThinDielectric { ior }
+    }
+}
+
+impl Material for ThinDielectric {
+    fn sample_f(&self, wo: &Vec3, _rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let mut reflectance = fresnel_dielectric(abs_cos_theta(wo), self.ior);
+        if reflectance < 1.0 {
+            // Each pass through the slab reflects R and transmits T at both faces
+            reflectance += (1.0 - reflectance).powi(2) * reflectance / (1.0 - reflectance.powi(2));
+        }
+        let transmittance = 1.0 - reflectance;
+
+        let (wi, pdf) = if rng.gen::<f32>() < reflectance {
+            (Vec3::new(-wo.x, -wo.y, wo.z), reflectance)
+        } else {
+            (-*wo, transmittance)
+        };
+
+        Some(BSDFSample {
+            wi,
+            f: Colour::new(1.0, 1.0, 1.0) * pdf / abs_cos_theta(&wi),
+            pdf,
+            specular: true,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// Shades whichever side of a surface was hit as the front of that side's material, for surfaces
+// with no inside like leaves and paper, or panels painted differently on each side
+pub struct TwoSided {
+    front: Arc<dyn Material>,
+    back: Arc<dyn Material>,
+}
+
+impl TwoSided {
+    // Back faces are mirrored through the surface so their material sees them as front faces
+    #[inline]
+    fn side(&self, rec: &IntersectRecord) -> (&dyn Material, IntersectRecord, f32) {
+        let front_rec = IntersectRecord {
+            front_face: true,
+            ..*rec
+        };
+        if rec.front_face {
+            (self.front.as_ref(), front_rec, 1.0)
+        } else {
+            (self.back.as_ref(), front_rec, -1.0)
+        }
+    }
+}
+
+#[inline]
+fn flip_z(w: &Vec3, sign: f32) -> Vec3 {
+    Vec3::new(w.x, w.y, w.z * sign)
+}
+
+impl Material for TwoSided {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        let (material, rec, sign) = self.side(rec);
+        material.f(&flip_z(wo, sign), &flip_z(wi, sign), &rec)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        let (material, rec, sign) = self.side(rec);
+        let sample = material.sample_f(&flip_z(wo, sign), &rec, rng)?;
+
+        Some(BSDFSample {
+            wi: flip_z(&sample.wi, sign),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        let (material, rec, sign) = self.side(rec);
+        material.pdf(&flip_z(wo, sign), &flip_z(wi, sign), &rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.front.is_specular() && self.back.is_specular()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.front.is_emissive() || self.back.is_emissive()
+    }
+
+    // Sampled points on lights are always front faces, so emission comes from the front to match
+    fn emitted(&self, wo: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.front.emitted(wo, rec)
+    }
+
+    // The inside of the shape is behind the front face, so only the front can lead into it
+    fn interior(&self, rec: &IntersectRecord) -> Option<Medium> {
+        self.front.interior(rec)
+    }
+
+    fn perturb(&self, rec: &mut IntersectRecord) {
+        if rec.front_face {
+            self.front.perturb(rec);
+        } else {
+            self.back.perturb(rec);
+        }
+    }
+}
+
+// Another material with bump or normal mapping
+pub struct Perturbed {
+    material: Arc<dyn Material>,
+    modifier: NormalModifier,
+}
+
+impl Material for Perturbed {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.material.f(wo, wi, rec)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        self.material.sample_f(wo, rec, rng)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        self.material.pdf(wo, wi, rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.material.is_specular()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn emitted(&self, wo: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.material.emitted(wo, rec)
+    }
+
+    fn interior(&self, rec: &IntersectRecord) -> Option<Medium> {
+        self.material.interior(rec)
+    }
+
+    // The wrapped material's own perturbation goes first, so modifiers can be stacked
+    fn perturb(&self, rec: &mut IntersectRecord) {
+        self.material.perturb(rec);
+        self.modifier.apply(rec);
+    }
+}
+
+// Smooth surfaces use a delta lobe instead of a microfacet distribution
+// Roughness along the two tangent directions of the shading frame, either fixed or varying over
+// the surface with a pair of scalar textures. Fixed roughness is turned into a distribution once
+#[derive(Clone)]
+pub enum Roughness {
+    Constant(Option<TrowbridgeReitz>),
+    Textured(Arc<dyn Texture>, Arc<dyn Texture>),
+}
+
+impl Roughness {
+    pub fn new(roughness_u: f32, roughness_v: f32) -> Roughness {
+        Roughness::Constant(microfacet_distribution(roughness_u, roughness_v))
+    }
+
+    // Distribution at a hit, or `None` where it's perfectly smooth
+    #[inline]
+    fn distribution(&self, rec: &IntersectRecord) -> Option<TrowbridgeReitz> {
+        match self {
+            Roughness::Constant(distribution) => *distribution,
+            Roughness::Textured(roughness_u, roughness_v) => {
+                let roughness = |texture: &Arc<dyn Texture>| {
+                    texture.evaluate_scalar(rec).clamp(0.0, 1.0) as f32
+                };
+                microfacet_distribution(roughness(roughness_u), roughness(roughness_v))
+            }
+        }
+    }
+
+    // Whether every hit is perfectly smooth. Textured roughness may not be anywhere
+    fn is_smooth(&self) -> bool {
+        matches!(self, Roughness::Constant(None))
+    }
+}
+
+fn microfacet_distribution(roughness_u: f32, roughness_v: f32) -> Option<TrowbridgeReitz> {
+    if roughness_u == 0.0 && roughness_v == 0.0 {
+        None
+    } else {
+        Some(TrowbridgeReitz::from_roughness(roughness_u, roughness_v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersectable::SurfaceDifferentials;
+    use crate::principled::{Principled, PrincipledParameters};
+    use rand::SeedableRng;
+
+    // Hit on the front of a surface lying in the xy plane, so world and local directions match
+    fn flat_record() -> IntersectRecord {
+        IntersectRecord {
+            point: Vec3::zero(),
+            object_point: Vec3::zero(),
+            normal: Vec3::unit_z(),
+            shading_normal: Vec3::unit_z(),
+            uv: Vec2::zero(),
+            dpdu: Vec3::unit_x(),
+            dpdv: Vec3::unit_y(),
+            object_dpdu: Vec3::unit_x(),
+            object_dpdv: Vec3::unit_y(),
+            differentials: SurfaceDifferentials::default(),
+            vertex_colour: None,
+            front_face: true,
+            material_id: MaterialID(0),
+        }
+    }
+
+    // Directions spread over the whole sphere, both above and below the surface, including along
+    // the normal where Oren-Nayar has no azimuth
+    fn direction_grid() -> Vec<Vec3> {
+        let mut directions = Vec::new();
+        for i in 0..=8 {
+            let theta = i as f32 / 8.0 * PI;
+            for j in 0..8 {
+                let phi = j as f32 / 8.0 * 2.0 * PI;
+                directions.push(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ));
+            }
+        }
+
+        directions
+    }
+
+    // Direction at `cos_theta` from the normal
+    fn outgoing(cos_theta: f32) -> Vec3 {
+        let sin_theta = (1.0 - cos_theta.powi(2)).max(0.0).sqrt();
+        Vec3::new(sin_theta, 0.0, cos_theta)
+    }
+
+    // Fractions of the light arriving from `wo` that the material reflects and transmits,
+    // averaged over the channels, estimated from its own samples
+    fn scattered(material: &dyn Material, wo: &Vec3) -> (f64, f64) {
+        const SAMPLES: u32 = 20000;
+        let rec = flat_record();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut reflected = Colour::default();
+        let mut transmitted = Colour::default();
+        for _ in 0..SAMPLES {
+            if let Some(sample) = material.sample_f(wo, &rec, &mut rng) {
+                if same_hemisphere(wo, &sample.wi) {
+                    reflected += sample.throughput();
+                } else {
+                    transmitted += sample.throughput();
+                }
+            }
+        }
+        let average = |total: Colour| (total.r + total.g + total.b) / 3.0 / SAMPLES as f64;
+
+        (average(reflected), average(transmitted))
+    }
+
+    fn albedo(material: &dyn Material, wo: &Vec3) -> f64 {
+        let (reflected, transmitted) = scattered(material, wo);
+        reflected + transmitted
+    }
+
+    const ROUGHNESSES: [f32; 5] = [0.01, 0.1, 0.3, 0.6, 1.0];
+    const COS_THETAS: [f32; 3] = [0.2, 0.6, 1.0];
+
+    // Light can't be created by a passive surface, in a white furnace
+    #[test]
+    fn rough_materials_conserve_energy() {
+        let white = Colour::new(1.0, 1.0, 1.0);
+        for &roughness in &ROUGHNESSES {
+            let materials: [(&str, Box<dyn Material>); 3] = [
+                ("metal", Box::new(Metal::new(white, roughness))),
+                (
+                    "gold",
+                    Box::new(Metal::preset("gold", roughness, roughness).unwrap()),
+                ),
+                // Entering the glass, as radiance leaving it is scaled up by the change in index
+                (
+                    "glass",
+                    Box::new(Dielectric::rough(1.5, roughness, roughness)),
+                ),
+            ];
+            for (name, material) in &materials {
+                for &cos_theta in &COS_THETAS {
+                    let albedo = albedo(material.as_ref(), &outgoing(cos_theta));
+                    assert!(
+                        albedo <= 1.01,
+                        "{} with roughness {} at cos(theta) {} has albedo {}",
+                        name,
+                        roughness,
+                        cos_theta,
+                        albedo
+                    );
+                }
+            }
+        }
+    }
+
+    // The transmission lobe's own reflection is the only one off the part of a principled material
+    // that transmits, so smooth principled glass reflects one Fresnel term, and no more light than
+    // arrives when what enters is scaled back up by the change in index
+    #[test]
+    fn principled_transmission_reflects_once() {
+        let ior = 1.5;
+        for &cos_theta in &COS_THETAS {
+            let glass = Principled::new(&PrincipledParameters {
+                base_colour: Colour::new(1.0, 1.0, 1.0),
+                roughness: 0.0,
+                transmission: 1.0,
+                ior,
+                ..PrincipledParameters::default()
+            });
+            let (reflected, _) = scattered(&glass, &outgoing(cos_theta));
+            let fresnel = fresnel_dielectric(cos_theta, ior) as f64;
+            assert!(
+                (reflected - fresnel).abs() <= 0.01,
+                "reflected {} at cos(theta) {}, expected {}",
+                reflected,
+                cos_theta,
+                fresnel
+            );
+        }
+
+        for &roughness in &ROUGHNESSES {
+            for &metallic in &[0.0, 0.5] {
+                let material = Principled::new(&PrincipledParameters {
+                    base_colour: Colour::new(1.0, 1.0, 1.0),
+                    metallic,
+                    roughness,
+                    transmission: 1.0,
+                    ior,
+                    ..PrincipledParameters::default()
+                });
+                for &cos_theta in &COS_THETAS {
+                    let (reflected, transmitted) = scattered(&material, &outgoing(cos_theta));
+                    let albedo = reflected + transmitted * (ior * ior) as f64;
+                    assert!(
+                        albedo <= 1.01,
+                        "metallic {} with roughness {} at cos(theta) {} has albedo {}",
+                        metallic,
+                        roughness,
+                        cos_theta,
+                        albedo
+                    );
+                }
+            }
+        }
+    }
+
+    // Roughness from a texture is evaluated at each hit, smooth where it's zero
+    #[test]
+    fn textured_roughness_varies_over_the_surface() {
+        struct AlongU;
+        impl Texture for AlongU {
+            fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+                let u = rec.uv.x as f64;
+                Colour::new(u, u, u)
+            }
+        }
+        let roughness: Arc<dyn Texture> = Arc::new(AlongU);
+        let metal = Metal::textured(
+            Arc::new(ConstantTexture::new(Colour::new(1.0, 1.0, 1.0))),
+            Roughness::Textured(roughness.clone(), roughness),
+        );
+        assert!(!metal.is_specular());
+
+        let wo = outgoing(0.6);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut rec = flat_record();
+        let smooth = metal.sample_f(&wo, &rec, &mut rng).unwrap();
+        assert!(smooth.specular);
+        assert_eq!(
+            [smooth.wi.x, smooth.wi.y, smooth.wi.z],
+            [-wo.x, -wo.y, wo.z]
+        );
+
+        rec.uv = Vec2::new(1.0, 0.0);
+        let rough = (0..16)
+            .find_map(|_| metal.sample_f(&wo, &rec, &mut rng))
+            .unwrap();
+        assert!(!rough.specular);
+        assert!(metal.pdf(&wo, &rough.wi, &rec) > 0.0);
+    }
+
+    // Only masking and shadowing lose light from a perfect conductor, which hardly happens when
+    // it's nearly smooth
+    #[test]
+    fn smooth_white_metal_reflects_everything() {
+        let metal = Metal::new(Colour::new(1.0, 1.0, 1.0), 0.01);
+        for &cos_theta in &COS_THETAS {
+            let albedo = albedo(&metal, &outgoing(cos_theta));
+            assert!(
+                (albedo - 1.0).abs() <= 0.02,
+                "albedo at cos(theta) {} is {}",
+                cos_theta,
+                albedo
+            );
+        }
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let albedo = Colour::new(0.8, 0.5, 0.2);
+        let oren_nayar = OrenNayar::new(albedo, 0.0);
+        let diffuse = Diffuse::new(albedo);
+        let rec = flat_record();
+
+        for wo in direction_grid() {
+            for wi in direction_grid() {
+                let expected = diffuse.f(&wo, &wi, &rec);
+                let f = oren_nayar.f(&wo, &wi, &rec);
+                for (a, b) in [(f.r, expected.r), (f.g, expected.g), (f.b, expected.b)] {
+                    assert!(
+                        (a - b).abs() <= 1e-6,
+                        "f({:?}, {:?}) is {:?} rather than {:?}",
+                        wo,
+                        wi,
+                        f,
+                        expected
+                    );
+                }
+
+                let expected = diffuse.pdf(&wo, &wi, &rec);
+                let pdf = oren_nayar.pdf(&wo, &wi, &rec);
+                assert!(
+                    (pdf - expected).abs() <= 1e-6,
+                    "pdf({:?}, {:?}) is {} rather than {}",
+                    wo,
+                    wi,
+                    pdf,
+                    expected
+                );
+            }
+        }
     }
 }
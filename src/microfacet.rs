@@ -0,0 +1,117 @@
+use crate::bsdf::{abs_cos_theta, cos2_theta, tan2_theta};
+use std::f32::consts::PI;
+use ultraviolet::{Vec2, Vec3};
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals in the local shading frame, with
+// separate roughness along the frame's s and t axes
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+#[allow(dead_code)]
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> TrowbridgeReitz {
+        TrowbridgeReitz { alpha_x, alpha_y }
+    }
+
+    // Perceptually linear roughness, squared as in Disney's BRDF. Tiny alphas are clamped to keep
+    // the distribution numerically stable; use a delta lobe for perfectly smooth surfaces
+    pub fn from_roughness(roughness_x: f32, roughness_y: f32) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(roughness_x.powi(2).max(1e-3), roughness_y.powi(2).max(1e-3))
+    }
+
+    pub fn d(&self, wh: &Vec3) -> f32 {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() || tan2_theta.is_nan() {
+            return 0.0;
+        }
+
+        let cos4_theta = cos2_theta(wh).powi(2);
+        let (cos2_phi, sin2_phi) = cos2_sin2_phi(wh);
+        let e = tan2_theta * (cos2_phi / self.alpha_x.powi(2) + sin2_phi / self.alpha_y.powi(2));
+
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e).powi(2))
+    }
+
+    // Smith's auxiliary function for the masked microfacet area per visible area
+    pub fn lambda(&self, w: &Vec3) -> f32 {
+        let tan2_theta = tan2_theta(w);
+        if tan2_theta.is_infinite() || tan2_theta.is_nan() {
+            return 0.0;
+        }
+
+        let (cos2_phi, sin2_phi) = cos2_sin2_phi(w);
+        let alpha2 = cos2_phi * self.alpha_x.powi(2) + sin2_phi * self.alpha_y.powi(2);
+
+        ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Distribution of normals visible from `w`
+    pub fn d_visible(&self, w: &Vec3, wh: &Vec3) -> f32 {
+        let cos_theta = abs_cos_theta(w);
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+
+        self.g1(w) / cos_theta * self.d(wh) * w.dot(*wh).abs()
+    }
+
+    // Samples a normal visible from `wo` (Heitz 2018), on the same side of the surface as `wo`
+    pub fn sample_wh(&self, wo: &Vec3, u: &Vec2) -> Vec3 {
+        let flip = wo.z < 0.0;
+        let wo = if flip { -*wo } else { *wo };
+
+        // Stretch the view direction so the distribution becomes a hemisphere
+        let wh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalized();
+        let length_sq = wh.x.powi(2) + wh.y.powi(2);
+        let t1 = if length_sq > 0.0 {
+            Vec3::new(-wh.y, wh.x, 0.0) / length_sq.sqrt()
+        } else {
+            Vec3::unit_x()
+        };
+        let t2 = wh.cross(t1);
+
+        // Sample the projected area of the hemisphere visible from the view direction
+        let r = u.x.sqrt();
+        let phi = 2.0 * PI * u.y;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1.powi(2)).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1.powi(2) - p2.powi(2)).max(0.0).sqrt();
+
+        // Unstretch back to the microfacet normal
+        let normal =
+            Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalized();
+
+        if flip {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    // Probability of `sample_wh` choosing `wh`
+    pub fn pdf(&self, wo: &Vec3, wh: &Vec3) -> f32 {
+        self.d_visible(wo, wh)
+    }
+}
+
+#[inline]
+fn cos2_sin2_phi(w: &Vec3) -> (f32, f32) {
+    let sin2_theta = w.x.powi(2) + w.y.powi(2);
+    if sin2_theta == 0.0 {
+        (1.0, 0.0)
+    } else {
+        (w.x.powi(2) / sin2_theta, w.y.powi(2) / sin2_theta)
+    }
+}
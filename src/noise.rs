@@ -0,0 +1,155 @@
+use crate::utils::mix_bits;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use ultraviolet::Vec3;
+
+// Ken Perlin's improved gradient noise, with the permutation shuffled from a seed so different
+// seeds give different but reproducible noise
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        // Repeated so lookups offset by a second index don't need wrapping
+        let mut permutation = [0; 512];
+        for (i, value) in permutation.iter_mut().enumerate() {
+            *value = table[i & 255];
+        }
+
+        Perlin { permutation }
+    }
+
+    // Noise in about [-1, 1], zero at every integer point and repeating every 256 units
+    pub fn noise(&self, p: &Vec3) -> f32 {
+        let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (dx, dy, dz) = (p.x - x, p.y - y, p.z - z);
+        let cell = |v: f32| (v as i64 & 255) as usize;
+        let (x, y, z) = (cell(x), cell(y), cell(z));
+        let perm = |i: usize| self.permutation[i] as usize;
+
+        let a = perm(x) + y;
+        let aa = perm(a) + z;
+        let ab = perm(a + 1) + z;
+        let b = perm(x + 1) + y;
+        let ba = perm(b) + z;
+        let bb = perm(b + 1) + z;
+
+        let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(perm(aa), dx, dy, dz),
+                    grad(perm(ba), dx - 1.0, dy, dz),
+                ),
+                lerp(
+                    u,
+                    grad(perm(ab), dx, dy - 1.0, dz),
+                    grad(perm(bb), dx - 1.0, dy - 1.0, dz),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(perm(aa + 1), dx, dy, dz - 1.0),
+                    grad(perm(ba + 1), dx - 1.0, dy, dz - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(perm(ab + 1), dx, dy - 1.0, dz - 1.0),
+                    grad(perm(bb + 1), dx - 1.0, dy - 1.0, dz - 1.0),
+                ),
+            ),
+        )
+    }
+
+    // Fractional Brownian motion, summing `octaves` of noise where each has about twice the
+    // frequency and `roughness` times the amplitude of the last
+    pub fn fbm(&self, p: &Vec3, roughness: f32, octaves: u32) -> f32 {
+        self.octaves(p, roughness, octaves, |noise| noise)
+    }
+
+    // Like `fbm` but summing the absolute noise, which creases where it crosses zero
+    pub fn turbulence(&self, p: &Vec3, roughness: f32, octaves: u32) -> f32 {
+        self.octaves(p, roughness, octaves, f32::abs)
+    }
+
+    fn octaves(&self, p: &Vec3, roughness: f32, octaves: u32, shape: fn(f32) -> f32) -> f32 {
+        let mut sum = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves {
+            sum += amplitude * shape(self.noise(&(*p * frequency)));
+            // Slightly off doubling so the octaves' lattices don't line up
+            frequency *= 1.99;
+            amplitude *= roughness;
+        }
+
+        sum
+    }
+}
+
+#[inline]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline]
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// Dot product with one of twelve gradients along the cube's edges, picked by the hash
+#[inline]
+fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Worley's cellular noise: the distance from `p` to the nearest of a set of feature points
+// scattered one per unit cell, placed by hashing the cell with the seed
+pub fn worley(p: &Vec3, seed: u64) -> f32 {
+    let cell = Vec3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let mut nearest_sq = f32::INFINITY;
+
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let neighbour = cell + Vec3::new(dx as f32, dy as f32, dz as f32);
+                let feature = neighbour + feature_offset(&neighbour, seed);
+                nearest_sq = nearest_sq.min((feature - *p).mag_sq());
+            }
+        }
+    }
+
+    nearest_sq.sqrt()
+}
+
+// Position of a cell's feature point within it, with 21 bits of the hash per axis
+#[inline]
+fn feature_offset(cell: &Vec3, seed: u64) -> Vec3 {
+    let mut hash = mix_bits(seed);
+    for value in [cell.x, cell.y, cell.z] {
+        hash = mix_bits(hash ^ value as i64 as u64);
+    }
+    let unit = |bits: u64| (bits & 0x1F_FFFF) as f32 / (1u64 << 21) as f32;
+
+    Vec3::new(unit(hash), unit(hash >> 21), unit(hash >> 42))
+}
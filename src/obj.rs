@@ -0,0 +1,531 @@
+use crate::colour::Colour;
+use crate::material::{Diffuse, Emissive, MaterialID};
+use crate::scene::Scene;
+use crate::triangle::TriangleMesh;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ultraviolet::{Mat4, Vec2, Vec3};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: Colour,
+    pub emissive: Colour,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            diffuse: Colour::new(0.8, 0.8, 0.8),
+            emissive: Colour::default(),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emissive.r > 0.0 || self.emissive.g > 0.0 || self.emissive.b > 0.0
+    }
+}
+
+// A group of faces sharing a material, with vertices de-duplicated across faces
+#[derive(Default)]
+pub struct ObjMesh {
+    pub material: Option<String>,
+    pub indices: Vec<u32>,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+}
+
+#[derive(Default)]
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+// Vertex reference of a face corner: position, uv and normal indices
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct MeshBuilder {
+    mesh: ObjMesh,
+    vertices: HashMap<VertexKey, u32>,
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn vertex(
+        &mut self,
+        key: VertexKey,
+        positions: &[Vec3],
+        uvs: &[Vec2],
+        normals: &[Vec3],
+    ) -> u32 {
+        if let Some(index) = self.vertices.get(&key) {
+            return *index;
+        }
+
+        let (position, uv, normal) = key;
+        let index = self.mesh.positions.len() as u32;
+        self.mesh.positions.push(positions[position]);
+        match uv {
+            Some(uv) => self.mesh.uvs.push(uvs[uv]),
+            None => {
+                self.missing_uvs = true;
+                self.mesh.uvs.push(Vec2::zero());
+            }
+        }
+        match normal {
+            Some(normal) => self.mesh.normals.push(normals[normal]),
+            None => {
+                self.missing_normals = true;
+                self.mesh.normals.push(Vec3::zero());
+            }
+        }
+        self.vertices.insert(key, index);
+
+        index
+    }
+
+    fn build(mut self) -> ObjMesh {
+        // Attributes are all-or-nothing per mesh
+        if self.missing_normals {
+            self.mesh.normals.clear();
+        }
+        if self.missing_uvs {
+            self.mesh.uvs.clear();
+        }
+
+        self.mesh
+    }
+}
+
+impl ObjModel {
+    pub fn load(path: &Path) -> Result<ObjModel, ObjError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| ObjError::Io(path.to_path_buf(), err))?;
+        ObjModel::parse(&contents, path)
+    }
+
+    // Parses the contents of the OBJ file at `path`, which material libraries are relative to
+    fn parse(contents: &str, path: &Path) -> Result<ObjModel, ObjError> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let parse_error = |line: usize, message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line,
+            message,
+        };
+
+        let mut model = ObjModel::default();
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut normals = Vec::new();
+
+        let mut builders: Vec<MeshBuilder> = Vec::new();
+        let mut current: Option<usize> = None;
+        let mut current_material: Option<String> = None;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line_number = i + 1;
+            let mut tokens = line.split('#').next().unwrap().split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let args: Vec<&str> = tokens.collect();
+
+            match keyword {
+                // Anything after the position, like a weight or the common vertex colour
+                // extension, is ignored
+                "v" => positions.push(Vec3::from(
+                    parse_leading_floats::<3>(&args, 3)
+                        .map_err(|err| parse_error(line_number, err))?,
+                )),
+                // `v` defaults to zero, and the optional third texture coordinate is ignored
+                "vt" => uvs.push(Vec2::from(
+                    parse_leading_floats::<2>(&args, 1)
+                        .map_err(|err| parse_error(line_number, err))?,
+                )),
+                "vn" => normals.push(Vec3::from(
+                    parse_floats::<3>(&args).map_err(|err| parse_error(line_number, err))?,
+                )),
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(parse_error(
+                            line_number,
+                            "faces need at least three vertices".to_string(),
+                        ));
+                    }
+
+                    let mut keys = Vec::with_capacity(args.len());
+                    for arg in args.iter() {
+                        keys.push(
+                            parse_face_vertex(arg, positions.len(), uvs.len(), normals.len())
+                                .map_err(|err| parse_error(line_number, err))?,
+                        );
+                    }
+
+                    let index = match current {
+                        Some(index) => index,
+                        None => {
+                            builders.push(MeshBuilder::default());
+                            builders.last_mut().unwrap().mesh.material = current_material.clone();
+                            builders.len() - 1
+                        }
+                    };
+                    current = Some(index);
+
+                    // Polygons are triangulated as a fan around the first vertex
+                    let builder = &mut builders[index];
+                    let first = builder.vertex(keys[0], &positions, &uvs, &normals);
+                    for pair in keys[1..].windows(2) {
+                        let second = builder.vertex(pair[0], &positions, &uvs, &normals);
+                        let third = builder.vertex(pair[1], &positions, &uvs, &normals);
+                        builder
+                            .mesh
+                            .indices
+                            .extend_from_slice(&[first, second, third]);
+                    }
+                }
+                "usemtl" => {
+                    let name = args.first().map(|name| name.to_string());
+                    current = builders.iter().position(|b| b.mesh.material == name);
+                    current_material = name;
+                }
+                "mtllib" => {
+                    for library in args.iter() {
+                        model.materials.extend(load_mtl(&directory.join(library))?);
+                    }
+                }
+                // Groups, objects, smoothing groups and anything else don't affect the geometry
+                _ => {}
+            }
+        }
+
+        model.meshes = builders
+            .into_iter()
+            .map(MeshBuilder::build)
+            .filter(|mesh| !mesh.indices.is_empty())
+            .collect();
+
+        Ok(model)
+    }
+
+    // Uses `material_override` for every face if given, otherwise converts the MTL materials
+    pub fn add_to_scene(
+        &self,
+        scene: &mut Scene,
+        object_to_world: Mat4,
+        world_to_object: Mat4,
+        material_override: Option<MaterialID>,
+        reverse_orientation: bool,
+    ) {
+        let mut material_ids: HashMap<Option<&str>, MaterialID> = HashMap::new();
+
+        for mesh in self.meshes.iter() {
+            let name = mesh.material.as_deref();
+            let material_id = match material_override {
+                Some(material_id) => material_id,
+                None => *material_ids
+                    .entry(name)
+                    .or_insert_with(|| self.add_material(scene, name)),
+            };
+
+            let triangle_mesh = Arc::new(TriangleMesh::new(
+                object_to_world,
+                world_to_object,
+                mesh.indices.clone(),
+                mesh.positions.clone(),
+                mesh.normals.clone(),
+                mesh.uvs.clone(),
+                Vec::new(),
+                reverse_orientation,
+            ));
+            for triangle in TriangleMesh::triangles(&triangle_mesh, material_id) {
+                scene.add_object(triangle);
+            }
+        }
+    }
+
+    fn add_material(&self, scene: &mut Scene, name: Option<&str>) -> MaterialID {
+        let material = name.and_then(|name| self.materials.iter().find(|m| m.name == name));
+
+        match material {
+            Some(material) if material.is_emissive() => {
+                scene.add_material(Box::new(Emissive::new(material.emissive, 1.0)))
+            }
+            Some(material) => scene.add_material(Box::new(Diffuse::new(material.diffuse))),
+            None => scene.add_material(Box::new(Diffuse::new(ObjMaterial::new("").diffuse))),
+        }
+    }
+}
+
+pub fn load_mtl(path: &Path) -> Result<Vec<ObjMaterial>, ObjError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| ObjError::Io(path.to_path_buf(), err))?;
+    let parse_error = |line: usize, message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        let mut tokens = line.split('#').next().unwrap().split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "newmtl" => match args.first() {
+                Some(name) => materials.push(ObjMaterial::new(name)),
+                None => {
+                    return Err(parse_error(
+                        line_number,
+                        "missing material name".to_string(),
+                    ))
+                }
+            },
+            "Kd" | "Ke" => {
+                let [r, g, b] =
+                    parse_floats::<3>(&args).map_err(|err| parse_error(line_number, err))?;
+                let colour = Colour::new_f32(r, g, b);
+                let material = match materials.last_mut() {
+                    Some(material) => material,
+                    None => {
+                        return Err(parse_error(
+                            line_number,
+                            format!("`{}` before any `newmtl`", keyword),
+                        ))
+                    }
+                };
+
+                if keyword == "Kd" {
+                    material.diffuse = colour;
+                } else {
+                    material.emissive = colour;
+                }
+            }
+            // Only diffuse and emissive colours have an equivalent material
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+fn parse_floats<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() != N {
+        return Err(format!("expected {} numbers, found {}", N, args.len()));
+    }
+
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args.iter()) {
+        *value = arg
+            .parse()
+            .map_err(|_| format!("`{}` isn't a number", arg))?;
+    }
+
+    Ok(values)
+}
+
+// Parses the first `N` numbers of a line that needs at least `required` of them, leaving any
+// missing ones at zero and ignoring any extras
+fn parse_leading_floats<const N: usize>(
+    args: &[&str],
+    required: usize,
+) -> Result<[f32; N], String> {
+    if args.len() < required {
+        let plural = if required == 1 { "" } else { "s" };
+        return Err(format!(
+            "expected at least {} number{}, found {}",
+            required,
+            plural,
+            args.len()
+        ));
+    }
+
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args.iter()) {
+        *value = arg
+            .parse()
+            .map_err(|_| format!("`{}` isn't a number", arg))?;
+    }
+
+    Ok(values)
+}
+
+// Resolves 1-based (or negative, relative) `v/vt/vn` indices to 0-based ones
+fn parse_face_vertex(
+    arg: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<VertexKey, String> {
+    let mut parts = arg.split('/');
+    let resolve = |part: Option<&str>, count: usize| -> Result<Option<usize>, String> {
+        match part {
+            None | Some("") => Ok(None),
+            Some(part) => {
+                let index: i64 = part
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't a valid vertex index", part))?;
+                let resolved = if index > 0 {
+                    index - 1
+                } else {
+                    count as i64 + index
+                };
+
+                if index == 0 || resolved < 0 || resolved >= count as i64 {
+                    Err(format!("vertex index `{}` is out of range", part))
+                } else {
+                    Ok(Some(resolved as usize))
+                }
+            }
+        }
+    };
+
+    let position = resolve(parts.next(), position_count)?
+        .ok_or_else(|| format!("face vertex `{}` is missing a position", arg))?;
+    let uv = resolve(parts.next(), uv_count)?;
+    let normal = resolve(parts.next(), normal_count)?;
+
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<ObjModel, ObjError> {
+        ObjModel::parse(contents, Path::new("test.obj"))
+    }
+
+    fn parse_error(contents: &str) -> (usize, String) {
+        match parse(contents) {
+            Ok(_) => panic!("model should fail to parse"),
+            Err(ObjError::Parse { line, message, .. }) => (line, message),
+            Err(err) => panic!("expected a parse error, found `{}`", err),
+        }
+    }
+
+    #[test]
+    fn leading_floats_fill_in_and_ignore_extras() {
+        assert_eq!(parse_leading_floats::<2>(&["0.5"], 1), Ok([0.5, 0.0]));
+        assert_eq!(
+            parse_leading_floats::<2>(&["0.5", "0.25", "1"], 1),
+            Ok([0.5, 0.25])
+        );
+        assert_eq!(
+            parse_leading_floats::<3>(&["1", "2", "3", "1", "0.5", "0.5", "0.5"], 3),
+            Ok([1.0, 2.0, 3.0])
+        );
+        assert!(parse_leading_floats::<3>(&["1", "2"], 3).is_err());
+        assert!(parse_leading_floats::<2>(&[], 1).is_err());
+        assert!(parse_leading_floats::<3>(&["1", "x", "3"], 3).is_err());
+    }
+
+    #[test]
+    fn face_vertices_resolve_indices() {
+        assert_eq!(parse_face_vertex("1", 3, 0, 0), Ok((0, None, None)));
+        assert_eq!(
+            parse_face_vertex("3/2/1", 3, 2, 1),
+            Ok((2, Some(1), Some(0)))
+        );
+        assert_eq!(parse_face_vertex("2//1", 3, 0, 1), Ok((1, None, Some(0))));
+        // Negative indices count back from the latest vertex
+        assert_eq!(parse_face_vertex("-1/-2", 3, 2, 0), Ok((2, Some(0), None)));
+    }
+
+    #[test]
+    fn out_of_range_face_vertices_are_errors() {
+        assert!(parse_face_vertex("0", 3, 0, 0).is_err());
+        assert!(parse_face_vertex("4", 3, 0, 0).is_err());
+        assert!(parse_face_vertex("-4", 3, 0, 0).is_err());
+        assert!(parse_face_vertex("1/2", 3, 1, 0).is_err());
+        assert!(parse_face_vertex("1//x", 3, 0, 1).is_err());
+        assert!(parse_face_vertex("/1", 3, 1, 0).is_err());
+    }
+
+    #[test]
+    fn parses_quads_with_relative_indices() {
+        let model = parse(
+            "# Unit quad, with colours after the positions and a 3D texture coordinate
+v 0 0 0 1 0 0
+v 1 0 0 0 1 0
+v 1 1 0 0 0 1
+v 0 1 0 1 1 1
+vt 0 0 0
+vt 1
+vt 1 1
+vt 0 1
+f -4/1 -3/2 -2/3 -1/4
+",
+        )
+        .unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        let positions: Vec<[f32; 3]> = mesh.positions.iter().map(|p| [p.x, p.y, p.z]).collect();
+        assert_eq!(
+            positions,
+            [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0]
+            ]
+        );
+        let uvs: Vec<[f32; 2]> = mesh.uvs.iter().map(|uv| [uv.x, uv.y]).collect();
+        assert_eq!(uvs, [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_faces_are_errors() {
+        let (line, message) = parse_error(
+            "v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 4
+",
+        );
+        assert_eq!(line, 4);
+        assert!(message.contains("out of range"), "{}", message);
+
+        let (line, message) = parse_error("v 0 0\n");
+        assert_eq!(line, 1);
+        assert!(message.contains("at least 3 numbers"), "{}", message);
+    }
+}
@@ -0,0 +1,926 @@
+use crate::camera::Camera;
+use crate::colour::Colour;
+use crate::cylinder::Cylinder;
+use crate::material::{
+    conductor_preset, Dielectric, Diffuse, Emissive, Material, MaterialID, Metal, OrenNayar,
+    Plastic,
+};
+use crate::ply::PlyMesh;
+use crate::principled::{Principled, PrincipledParameters};
+use crate::scene::Scene;
+use crate::scene_file::RenderDescription;
+use crate::sphere::Sphere;
+use crate::transform::{self, transform_point, transform_vector};
+use crate::triangle::TriangleMesh;
+use crate::utils::linear_determinant;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ultraviolet::{Mat4, Vec2, Vec3, Vec4};
+
+#[derive(Debug)]
+pub enum PbrtError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for PbrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PbrtError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            PbrtError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for PbrtError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Identifier(String),
+    String(String),
+    Number(f64),
+    OpenBracket,
+    CloseBracket,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        let kind = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '#' => {
+                while let Some(c) = chars.peek() {
+                    if *c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+                continue;
+            }
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return Err((line, "unterminated string".to_string()));
+                        }
+                        Some(c) => string.push(c),
+                    }
+                }
+                TokenKind::String(string)
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_ascii_digit() || "+-.eE".contains(*c) {
+                        number.push(*c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match number.parse() {
+                    Ok(number) => TokenKind::Number(number),
+                    Err(_) => return Err((line, format!("invalid number `{}`", number))),
+                }
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut identifier = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || *c == '_' {
+                        identifier.push(*c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                TokenKind::Identifier(identifier)
+            }
+            c => return Err((line, format!("unexpected character `{}`", c))),
+        };
+
+        tokens.push(Token { kind, line });
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    String(String),
+}
+
+struct Param {
+    type_name: String,
+    name: String,
+    values: Vec<Value>,
+    used: Cell<bool>,
+}
+
+// Parameter list of a directive, tracking which parameters were read so the rest can be reported
+struct ParamList(Vec<Param>);
+
+impl ParamList {
+    fn parse(tokens: &[Token]) -> Result<ParamList, (usize, String)> {
+        let mut params = Vec::new();
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let line = tokens[i].line;
+            let declaration = match &tokens[i].kind {
+                TokenKind::String(declaration) => declaration,
+                _ => return Err((line, "expected a parameter declaration".to_string())),
+            };
+            let mut parts = declaration.split_whitespace();
+            let (type_name, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(type_name), Some(name), None) => (type_name, name),
+                _ => {
+                    return Err((
+                        line,
+                        format!("malformed parameter declaration `{}`", declaration),
+                    ))
+                }
+            };
+            i += 1;
+
+            let mut values = Vec::new();
+            match tokens.get(i).map(|token| &token.kind) {
+                Some(TokenKind::OpenBracket) => {
+                    i += 1;
+                    loop {
+                        match tokens.get(i).map(|token| &token.kind) {
+                            Some(TokenKind::CloseBracket) => break,
+                            Some(TokenKind::Number(number)) => values.push(Value::Number(*number)),
+                            Some(TokenKind::String(string)) => {
+                                values.push(Value::String(string.clone()))
+                            }
+                            _ => return Err((line, format!("unterminated values for `{}`", name))),
+                        }
+                        i += 1;
+                    }
+                }
+                Some(TokenKind::Number(number)) => values.push(Value::Number(*number)),
+                Some(TokenKind::String(string)) => values.push(Value::String(string.clone())),
+                _ => return Err((line, format!("missing value for `{}`", name))),
+            }
+            i += 1;
+
+            params.push(Param {
+                type_name: type_name.to_string(),
+                name: name.to_string(),
+                values,
+                used: Cell::new(false),
+            });
+        }
+
+        Ok(ParamList(params))
+    }
+
+    fn find(&self, type_names: &[&str], name: &str) -> Option<&Param> {
+        let param = self
+            .0
+            .iter()
+            .find(|param| param.name == name && type_names.contains(&param.type_name.as_str()))?;
+        param.used.set(true);
+
+        Some(param)
+    }
+
+    fn numbers(&self, type_names: &[&str], name: &str) -> Option<Vec<f64>> {
+        self.find(type_names, name).map(|param| {
+            param
+                .values
+                .iter()
+                .filter_map(|value| match value {
+                    Value::Number(number) => Some(*number),
+                    Value::String(_) => None,
+                })
+                .collect()
+        })
+    }
+
+    fn float(&self, name: &str) -> Option<f32> {
+        self.numbers(&["float"], name)
+            .and_then(|values| values.first().map(|value| *value as f32))
+    }
+
+    fn integer(&self, name: &str) -> Option<i64> {
+        self.numbers(&["integer"], name)
+            .and_then(|values| values.first().map(|value| *value as i64))
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.find(&["string"], name)
+            .and_then(|param| match param.values.first() {
+                Some(Value::String(string)) => Some(string.clone()),
+                _ => None,
+            })
+    }
+
+    fn rgb(&self, name: &str) -> Option<Colour> {
+        self.numbers(&["rgb", "color"], name).and_then(|values| {
+            if values.len() == 3 {
+                Some(Colour::new(values[0], values[1], values[2]))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn unused(&self) -> impl Iterator<Item = &Param> {
+        self.0.iter().filter(|param| !param.used.get())
+    }
+}
+
+// Camera placement in the renderer's world space, built once the aspect ratio is known
+pub struct PbrtCamera {
+    origin: Vec3,
+    direction: Vec3,
+    up: Vec3,
+    fov: f32,
+    lens_radius: f32,
+    focal_distance: f32,
+}
+
+impl Default for PbrtCamera {
+    fn default() -> Self {
+        PbrtCamera {
+            origin: Vec3::zero(),
+            direction: Vec3::unit_z(),
+            up: Vec3::unit_y(),
+            fov: 90.0,
+            lens_radius: 0.0,
+            focal_distance: 1e6,
+        }
+    }
+}
+
+pub struct PbrtScene {
+    pub scene: Scene,
+    pub camera: PbrtCamera,
+    pub render: RenderDescription,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    material: MaterialID,
+    area_light: Option<MaterialID>,
+    reverse_orientation: bool,
+}
+
+struct Parser {
+    scene: Scene,
+    camera: PbrtCamera,
+    render: RenderDescription,
+    warnings: Vec<String>,
+
+    ctm: Mat4,
+    graphics_state: GraphicsState,
+    attribute_stack: Vec<(Mat4, GraphicsState)>,
+    transform_stack: Vec<Mat4>,
+    named_materials: HashMap<String, MaterialID>,
+    named_coordinate_systems: HashMap<String, Mat4>,
+    directory: PathBuf,
+    // Files being parsed, each included by the one before, so include cycles can be caught
+    open_files: Vec<PathBuf>,
+}
+
+// pbrt uses a left-handed coordinate system, so scenes are mirrored in x to keep images the
+// right way round with the right-handed camera
+fn handedness_flip() -> Mat4 {
+    transform::scale(Vec3::new(-1.0, 1.0, 1.0))
+}
+
+impl PbrtScene {
+    pub fn load(path: &Path) -> Result<PbrtScene, PbrtError> {
+        let mut scene = Scene::default();
+        let default_material =
+            scene.add_material(Box::new(Diffuse::new(Colour::new(0.5, 0.5, 0.5))));
+
+        let mut parser = Parser {
+            scene,
+            camera: PbrtCamera::default(),
+            render: RenderDescription::default(),
+            warnings: Vec::new(),
+            ctm: Mat4::identity(),
+            graphics_state: GraphicsState {
+                material: default_material,
+                area_light: None,
+                reverse_orientation: false,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            named_materials: HashMap::new(),
+            named_coordinate_systems: HashMap::new(),
+            directory: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            open_files: Vec::new(),
+        };
+        parser.parse_file(path)?;
+
+        if parser.scene.objects.is_empty() {
+            return Err(PbrtError::Parse {
+                path: path.to_path_buf(),
+                line: 1,
+                message: "scene doesn't contain any supported shapes".to_string(),
+            });
+        }
+
+        Ok(PbrtScene {
+            scene: parser.scene,
+            camera: parser.camera,
+            render: parser.render,
+            warnings: parser.warnings,
+        })
+    }
+
+    pub fn build(mut self, aspect_ratio: f32) -> (Scene, Camera) {
+        self.scene.generate_bvh();
+
+        // pbrt's field of view spans the shorter image axis, but ours is always vertical
+        let mut fov = self.camera.fov;
+        if aspect_ratio < 1.0 {
+            fov = 2.0
+                * ((fov.to_radians() / 2.0).tan() / aspect_ratio)
+                    .atan()
+                    .to_degrees();
+        }
+        let (aperture, focus_distance) = if self.camera.lens_radius > 0.0 {
+            (2.0 * self.camera.lens_radius, self.camera.focal_distance)
+        } else {
+            (0.0, 1.0)
+        };
+
+        let camera = Camera::new(
+            self.camera.origin,
+            self.camera.origin + self.camera.direction,
+            self.camera.up,
+            fov,
+            aspect_ratio,
+            aperture,
+            focus_distance,
+            0.001,
+            f32::INFINITY,
+        );
+
+        (self.scene, camera)
+    }
+}
+
+impl Parser {
+    fn parse_file(&mut self, path: &Path) -> Result<(), PbrtError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| PbrtError::Io(path.to_path_buf(), err))?;
+        self.open_files.push(canonical(path));
+        let result = self.parse_source(path, &source);
+        self.open_files.pop();
+
+        result
+    }
+
+    fn parse_source(&mut self, path: &Path, source: &str) -> Result<(), PbrtError> {
+        let parse_error = |(line, message): (usize, String)| PbrtError::Parse {
+            path: path.to_path_buf(),
+            line,
+            message,
+        };
+        let tokens = tokenize(source).map_err(parse_error)?;
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let line = tokens[i].line;
+            let directive = match &tokens[i].kind {
+                TokenKind::Identifier(directive) => directive.clone(),
+                _ => return Err(parse_error((line, "expected a directive".to_string()))),
+            };
+
+            // Arguments run up to the next directive
+            let start = i + 1;
+            i = start;
+            while i < tokens.len() && !matches!(tokens[i].kind, TokenKind::Identifier(_)) {
+                i += 1;
+            }
+
+            self.directive(&directive, &tokens[start..i], path, line)
+                .map_err(|message| parse_error((line, message)))?;
+        }
+
+        Ok(())
+    }
+
+    fn warn(&mut self, path: &Path, line: usize, message: String) {
+        self.warnings
+            .push(format!("{}:{}: {}", path.display(), line, message));
+    }
+
+    fn warn_unused(&mut self, path: &Path, line: usize, directive: &str, params: &ParamList) {
+        for param in params.unused() {
+            self.warnings.push(format!(
+                "{}:{}: ignoring unsupported parameter `{} {}` of `{}`",
+                path.display(),
+                line,
+                param.type_name,
+                param.name,
+                directive
+            ));
+        }
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        args: &[Token],
+        path: &Path,
+        line: usize,
+    ) -> Result<(), String> {
+        match directive {
+            "Identity" => self.ctm = Mat4::identity(),
+            "Translate" => {
+                let v = numbers(args, 3)?;
+                self.ctm = self.ctm * transform::translate(vec3(&v));
+            }
+            "Scale" => {
+                let v = numbers(args, 3)?;
+                if v.contains(&0.0) {
+                    return Err("scale can't be zero".to_string());
+                }
+                self.ctm = self.ctm * transform::scale(vec3(&v));
+            }
+            "Rotate" => {
+                let v = numbers(args, 4)?;
+                let axis = vec3(&v[1..]);
+                if axis.mag_sq() == 0.0 {
+                    return Err("rotation axis can't be zero".to_string());
+                }
+                self.ctm = self.ctm * transform::rotate(v[0] as f32, axis);
+            }
+            "LookAt" => {
+                let v = numbers(args, 9)?;
+                self.ctm = self.ctm * look_at(vec3(&v[0..3]), vec3(&v[3..6]), vec3(&v[6..9]))?;
+            }
+            "Transform" => self.ctm = matrix(args)?,
+            "ConcatTransform" => self.ctm = self.ctm * matrix(args)?,
+            "CoordinateSystem" => {
+                let (name, _) = name_and_params(args)?;
+                self.named_coordinate_systems.insert(name, self.ctm);
+            }
+            "CoordSysTransform" => {
+                let (name, _) = name_and_params(args)?;
+                match self.named_coordinate_systems.get(&name) {
+                    Some(m) => self.ctm = *m,
+                    None => self.warn(path, line, format!("unknown coordinate system `{}`", name)),
+                }
+            }
+            "Camera" => {
+                let (name, params) = name_and_params(args)?;
+                let camera_to_world = self.ctm.inversed();
+                self.named_coordinate_systems
+                    .insert("camera".to_string(), camera_to_world);
+                if name != "perspective" {
+                    self.warn(
+                        path,
+                        line,
+                        format!("unsupported camera `{}`, using perspective", name),
+                    );
+                }
+
+                let camera_to_world = handedness_flip() * camera_to_world;
+                self.camera = PbrtCamera {
+                    origin: transform_point(&camera_to_world, Vec3::zero()),
+                    direction: transform_vector(&camera_to_world, Vec3::unit_z()).normalized(),
+                    up: transform_vector(&camera_to_world, Vec3::unit_y()).normalized(),
+                    fov: params.float("fov").unwrap_or(90.0),
+                    lens_radius: params.float("lensradius").unwrap_or(0.0),
+                    focal_distance: params.float("focaldistance").unwrap_or(1e6),
+                };
+                self.warn_unused(path, line, directive, &params);
+            }
+            "Film" => {
+                let (_, params) = name_and_params(args)?;
+                self.render.width = params.integer("xresolution").map(|x| x.max(1) as u32);
+                self.render.height = params.integer("yresolution").map(|y| y.max(1) as u32);
+                // Output paths come from the command line
+                params.string("filename");
+                self.warn_unused(path, line, directive, &params);
+            }
+            "Sampler" => {
+                let (_, params) = name_and_params(args)?;
+                self.render.samples = params.integer("pixelsamples").map(|s| s.max(1) as u32);
+                self.warn_unused(path, line, directive, &params);
+            }
+            "Integrator" => {
+                let (_, params) = name_and_params(args)?;
+                self.render.max_depth = params.integer("maxdepth").map(|d| d.max(1) as u32);
+                self.warn_unused(path, line, directive, &params);
+            }
+            "PixelFilter" | "Accelerator" => {
+                self.warn(path, line, format!("ignoring `{}`", directive));
+            }
+            "WorldBegin" => {
+                self.ctm = Mat4::identity();
+                self.named_coordinate_systems
+                    .insert("world".to_string(), self.ctm);
+            }
+            "WorldEnd" => {}
+            "AttributeBegin" => self
+                .attribute_stack
+                .push((self.ctm, self.graphics_state.clone())),
+            "AttributeEnd" => match self.attribute_stack.pop() {
+                Some((ctm, graphics_state)) => {
+                    self.ctm = ctm;
+                    self.graphics_state = graphics_state;
+                }
+                None => return Err("unmatched `AttributeEnd`".to_string()),
+            },
+            "TransformBegin" => self.transform_stack.push(self.ctm),
+            "TransformEnd" => match self.transform_stack.pop() {
+                Some(ctm) => self.ctm = ctm,
+                None => return Err("unmatched `TransformEnd`".to_string()),
+            },
+            "ReverseOrientation" => {
+                self.graphics_state.reverse_orientation = !self.graphics_state.reverse_orientation
+            }
+            "Material" => {
+                let (name, params) = name_and_params(args)?;
+                self.graphics_state.material = self.material(&name, &params, path, line);
+            }
+            "MakeNamedMaterial" => {
+                let (name, params) = name_and_params(args)?;
+                let material_type = params.string("type").unwrap_or_default();
+                let material_id = self.material(&material_type, &params, path, line);
+                self.named_materials.insert(name, material_id);
+            }
+            "NamedMaterial" => {
+                let (name, _) = name_and_params(args)?;
+                match self.named_materials.get(&name) {
+                    Some(material_id) => self.graphics_state.material = *material_id,
+                    None => self.warn(path, line, format!("unknown named material `{}`", name)),
+                }
+            }
+            "AreaLightSource" => {
+                let (name, params) = name_and_params(args)?;
+                if name != "diffuse" {
+                    self.warn(path, line, format!("unsupported area light `{}`", name));
+                }
+                let radiance = params
+                    .rgb("L")
+                    .unwrap_or_else(|| Colour::new(1.0, 1.0, 1.0));
+                let scale = params.float("scale").unwrap_or(1.0);
+                self.graphics_state.area_light = Some(
+                    self.scene
+                        .add_material(Box::new(Emissive::new(radiance, scale))),
+                );
+                self.warn_unused(path, line, directive, &params);
+            }
+            "Shape" => {
+                let (name, params) = name_and_params(args)?;
+                self.shape(&name, &params, path, line)?;
+                self.warn_unused(path, line, directive, &params);
+            }
+            "Include" => {
+                let (name, _) = name_and_params(args)?;
+                let include = self.directory.join(name);
+                if self.open_files.contains(&canonical(&include)) {
+                    return Err(format!(
+                        "`{}` includes itself, directly or through other files",
+                        include.display()
+                    ));
+                }
+                self.parse_file(&include).map_err(|err| err.to_string())?;
+            }
+            _ => self.warn(
+                path,
+                line,
+                format!("ignoring unsupported directive `{}`", directive),
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn material(&mut self, name: &str, params: &ParamList, path: &Path, line: usize) -> MaterialID {
+        if name == "mix" {
+            let material_id = self.mix_material(params, path, line);
+            self.warn_unused(path, line, "Material", params);
+            return material_id;
+        }
+
+        let material: Box<dyn Material> = match name {
+            "mirror" => {
+                let reflectance = params
+                    .rgb("Kr")
+                    .unwrap_or_else(|| Colour::new(0.9, 0.9, 0.9));
+                Box::new(Metal::new(reflectance, 0.0))
+            }
+            // pbrt defaults to slightly rough copper
+            "metal" => {
+                let (copper_eta, copper_k) = conductor_preset("copper").unwrap();
+                let eta = params.rgb("eta").unwrap_or(copper_eta);
+                let k = params.rgb("k").unwrap_or(copper_k);
+                let roughness = params.float("roughness").unwrap_or(0.01);
+                let roughness_u = params.float("uroughness").unwrap_or(roughness);
+                let roughness_v = params.float("vroughness").unwrap_or(roughness);
+                Box::new(Metal::conductor(eta, k, roughness_u, roughness_v))
+            }
+            "disney" => {
+                let defaults = PrincipledParameters::default();
+                let float = |name, default| params.float(name).unwrap_or(default);
+                Box::new(Principled::new(&PrincipledParameters {
+                    base_colour: params.rgb("color").unwrap_or(defaults.base_colour),
+                    metallic: float("metallic", defaults.metallic),
+                    roughness: float("roughness", defaults.roughness),
+                    specular: defaults.specular,
+                    specular_tint: float("speculartint", defaults.specular_tint),
+                    sheen: float("sheen", defaults.sheen),
+                    sheen_tint: float("sheentint", defaults.sheen_tint),
+                    clearcoat: float("clearcoat", defaults.clearcoat),
+                    clearcoat_gloss: float("clearcoatgloss", defaults.clearcoat_gloss),
+                    transmission: float("spectrans", defaults.transmission),
+                    ior: float("eta", defaults.ior),
+                    anisotropic: float("anisotropic", defaults.anisotropic),
+                }))
+            }
+            "plastic" => {
+                let albedo = params
+                    .rgb("Kd")
+                    .unwrap_or_else(|| Colour::new(0.25, 0.25, 0.25));
+                let roughness = params.float("roughness").unwrap_or(0.1);
+                Box::new(Plastic::new(albedo, 1.5, roughness))
+            }
+            "glass" => {
+                let ior = params
+                    .float("eta")
+                    .or_else(|| params.float("index"))
+                    .unwrap_or(1.5);
+                let roughness_u = params.float("uroughness").unwrap_or(0.0);
+                let roughness_v = params.float("vroughness").unwrap_or(0.0);
+                Box::new(Dielectric::rough(ior, roughness_u, roughness_v))
+            }
+            _ => {
+                if name != "matte" {
+                    self.warn(
+                        path,
+                        line,
+                        format!("unsupported material `{}`, using matte", name),
+                    );
+                }
+                let albedo = params
+                    .rgb("Kd")
+                    .unwrap_or_else(|| Colour::new(0.5, 0.5, 0.5));
+                match params.float("sigma") {
+                    Some(sigma) if sigma > 0.0 => Box::new(OrenNayar::new(albedo, sigma)),
+                    _ => Box::new(Diffuse::new(albedo)),
+                }
+            }
+        };
+        let material_id = self.scene.add_material(material);
+        self.warn_unused(path, line, "Material", params);
+
+        material_id
+    }
+
+    // Mixes can only name materials defined before them, so they can't form a cycle
+    fn mix_material(&mut self, params: &ParamList, path: &Path, line: usize) -> MaterialID {
+        let mut mixed = [self.graphics_state.material; 2];
+        for (material_id, param) in mixed.iter_mut().zip(["namedmaterial1", "namedmaterial2"]) {
+            let name = params.string(param).unwrap_or_default();
+            match self.named_materials.get(&name) {
+                Some(named) => *material_id = *named,
+                None => self.warn(
+                    path,
+                    line,
+                    format!(
+                        "unknown named material `{}` in mix, using the current material",
+                        name
+                    ),
+                ),
+            }
+        }
+        let amount = params.float("amount").unwrap_or(0.5).clamp(0.0, 1.0);
+
+        self.scene
+            .materials
+            .add_mix(mixed[0], mixed[1], amount)
+            .unwrap()
+    }
+
+    fn shape(
+        &mut self,
+        name: &str,
+        params: &ParamList,
+        path: &Path,
+        line: usize,
+    ) -> Result<(), String> {
+        let object_to_world = handedness_flip() * self.ctm;
+        let world_to_object = object_to_world.inversed();
+        let material_id = self
+            .graphics_state
+            .area_light
+            .unwrap_or(self.graphics_state.material);
+        let reverse_orientation = self.graphics_state.reverse_orientation;
+
+        match name {
+            "sphere" => {
+                let radius = params.float("radius").unwrap_or(1.0);
+                self.scene.add_object(Box::new(Sphere::from_transform(
+                    object_to_world,
+                    world_to_object,
+                    radius,
+                    material_id,
+                    reverse_orientation,
+                )));
+            }
+            "cylinder" => {
+                let radius = params.float("radius").unwrap_or(1.0);
+                let z_min = params.float("zmin").unwrap_or(-1.0);
+                let z_max = params.float("zmax").unwrap_or(1.0);
+                self.scene.add_object(Box::new(Cylinder::from_transform(
+                    object_to_world,
+                    world_to_object,
+                    radius,
+                    z_min.min(z_max),
+                    z_min.max(z_max),
+                    material_id,
+                    reverse_orientation,
+                )));
+            }
+            "trianglemesh" => {
+                let positions: Vec<Vec3> = params
+                    .numbers(&["point", "point3"], "P")
+                    .ok_or("`trianglemesh` requires `point P`")?
+                    .chunks_exact(3)
+                    .map(vec3)
+                    .collect();
+                let indices: Vec<u32> = match params.numbers(&["integer"], "indices") {
+                    Some(indices) => indices.iter().map(|i| *i as u32).collect(),
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err("`trianglemesh` requires `integer indices`".to_string()),
+                };
+                if indices.len() % 3 != 0 {
+                    return Err("`trianglemesh` indices must come in threes".to_string());
+                }
+                if indices.iter().any(|i| *i as usize >= positions.len()) {
+                    return Err("`trianglemesh` index out of range".to_string());
+                }
+
+                let mut normals: Vec<Vec3> = params
+                    .numbers(&["normal", "normal3"], "N")
+                    .unwrap_or_default()
+                    .chunks_exact(3)
+                    .map(vec3)
+                    .collect();
+                let mut uvs: Vec<Vec2> = params
+                    .numbers(&["float", "point2"], "uv")
+                    .or_else(|| params.numbers(&["float", "point2"], "st"))
+                    .unwrap_or_default()
+                    .chunks_exact(2)
+                    .map(|uv| Vec2::new(uv[0] as f32, uv[1] as f32))
+                    .collect();
+                if normals.len() != positions.len() {
+                    normals.clear();
+                }
+                if uvs.len() != positions.len() {
+                    uvs.clear();
+                }
+
+                let mesh = Arc::new(TriangleMesh::new(
+                    object_to_world,
+                    world_to_object,
+                    indices,
+                    positions,
+                    normals,
+                    uvs,
+                    Vec::new(),
+                    reverse_orientation,
+                ));
+                for triangle in TriangleMesh::triangles(&mesh, material_id) {
+                    self.scene.add_object(triangle);
+                }
+            }
+            "plymesh" => {
+                let filename = params
+                    .string("filename")
+                    .ok_or("`plymesh` requires `string filename`")?;
+                let mesh =
+                    PlyMesh::load(&self.directory.join(filename)).map_err(|err| err.to_string())?;
+                mesh.add_to_scene(
+                    &mut self.scene,
+                    object_to_world,
+                    world_to_object,
+                    Some(material_id),
+                    reverse_orientation,
+                );
+            }
+            _ => self.warn(path, line, format!("ignoring unsupported shape `{}`", name)),
+        }
+
+        Ok(())
+    }
+}
+
+fn numbers(args: &[Token], count: usize) -> Result<Vec<f64>, String> {
+    let values: Vec<f64> = args
+        .iter()
+        .filter_map(|token| match token.kind {
+            TokenKind::Number(number) => Some(number),
+            _ => None,
+        })
+        .collect();
+    let is_bracketed = args.len() == count + 2
+        && args.first().map(|token| &token.kind) == Some(&TokenKind::OpenBracket)
+        && args.last().map(|token| &token.kind) == Some(&TokenKind::CloseBracket);
+
+    if values.len() == count && (args.len() == count || is_bracketed) {
+        Ok(values)
+    } else {
+        Err(format!("expected {} numbers", count))
+    }
+}
+
+fn name_and_params(args: &[Token]) -> Result<(String, ParamList), String> {
+    match args.first().map(|token| &token.kind) {
+        Some(TokenKind::String(name)) => {
+            let params = ParamList::parse(&args[1..]).map_err(|(_, message)| message)?;
+            Ok((name.clone(), params))
+        }
+        _ => Err("expected a quoted name".to_string()),
+    }
+}
+
+fn vec3(v: &[f64]) -> Vec3 {
+    Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32)
+}
+
+// pbrt lists matrices column by column
+fn matrix(args: &[Token]) -> Result<Mat4, String> {
+    let m = numbers(args, 16)?;
+    let column = |i: usize| {
+        Vec4::new(
+            m[i * 4] as f32,
+            m[i * 4 + 1] as f32,
+            m[i * 4 + 2] as f32,
+            m[i * 4 + 3] as f32,
+        )
+    };
+
+    let m = Mat4::new(column(0), column(1), column(2), column(3));
+    if linear_determinant(&m) == 0.0 {
+        return Err("transform matrix can't be inverted".to_string());
+    }
+
+    Ok(m)
+}
+
+// Path used to recognise a file however it's referred to, or the path itself if it can't be
+// resolved, in which case reading it fails anyway
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// World to camera transform of pbrt's left-handed look-at
+fn look_at(position: Vec3, look: Vec3, up: Vec3) -> Result<Mat4, String> {
+    let direction = (look - position).normalized();
+    let right = up.normalized().cross(direction);
+    if right.mag_sq() == 0.0 {
+        return Err("`LookAt` up vector is parallel to the view direction".to_string());
+    }
+    let right = right.normalized();
+    let new_up = direction.cross(right);
+
+    let camera_to_world = Mat4::new(
+        Vec4::new(right.x, right.y, right.z, 0.0),
+        Vec4::new(new_up.x, new_up.y, new_up.z, 0.0),
+        Vec4::new(direction.x, direction.y, direction.z, 0.0),
+        Vec4::new(position.x, position.y, position.z, 1.0),
+    );
+
+    Ok(camera_to_world.inversed())
+}
@@ -0,0 +1,697 @@
+use crate::colour::Colour;
+use crate::material::{Diffuse, MaterialID};
+use crate::scene::Scene;
+use crate::texture::VertexColour;
+use crate::triangle::TriangleMesh;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ultraviolet::{Mat4, Vec2, Vec3};
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(PathBuf, std::io::Error),
+    Header {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Body {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            PlyError::Header {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            PlyError::Body { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(s: &str) -> Option<ScalarType> {
+        match s {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // Integer colour channels are stored as 0-255 while float ones are already normalised
+    fn colour_scale(self) -> f64 {
+        match self {
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+            _ => 1.0 / 255.0,
+        }
+    }
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType),
+}
+
+struct Property {
+    name: String,
+    property_type: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn scalar_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| {
+            names.contains(&property.name.as_str())
+                && matches!(property.property_type, PropertyType::Scalar(_))
+        })
+    }
+}
+
+// Reads scalar values from the body in any encoding, widening everything to f64
+enum BodyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl<'a> BodyReader<'a> {
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64, String> {
+        match self {
+            BodyReader::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of data")?;
+                token
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't a number", token))
+            }
+            BodyReader::Binary { bytes, big_endian } => {
+                let size = scalar_type.size();
+                if bytes.len() < size {
+                    return Err("unexpected end of data".to_string());
+                }
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+
+                // Big-endian values are reversed so both byte orders decode the same way
+                let mut buffer = [0; 8];
+                buffer[..size].copy_from_slice(value);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let value = &buffer[..size];
+
+                Ok(match scalar_type {
+                    ScalarType::Int8 => value[0] as i8 as f64,
+                    ScalarType::UInt8 => value[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::UInt32 => u32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes(value.try_into().unwrap()),
+                })
+            }
+        }
+    }
+
+    fn read_index(&mut self, scalar_type: ScalarType) -> Result<usize, String> {
+        let value = self.read(scalar_type)?;
+        if value >= 0.0 && value.fract() == 0.0 {
+            Ok(value as usize)
+        } else {
+            Err(format!("`{}` isn't a valid index", value))
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PlyMesh {
+    pub indices: Vec<u32>,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub colours: Vec<Colour>,
+}
+
+impl PlyMesh {
+    pub fn load(path: &Path) -> Result<PlyMesh, PlyError> {
+        let data = std::fs::read(path).map_err(|err| PlyError::Io(path.to_path_buf(), err))?;
+        PlyMesh::parse(&data, path)
+    }
+
+    // Parses the contents of a PLY file, where `path` is only used to report errors
+    fn parse(data: &[u8], path: &Path) -> Result<PlyMesh, PlyError> {
+        let (format, elements, body) =
+            parse_header(data).map_err(|(line, message)| PlyError::Header {
+                path: path.to_path_buf(),
+                line,
+                message,
+            })?;
+
+        let reader = match format {
+            Format::Ascii => match std::str::from_utf8(body) {
+                Ok(body) => BodyReader::Ascii(body.split_ascii_whitespace()),
+                Err(_) => {
+                    return Err(PlyError::Body {
+                        path: path.to_path_buf(),
+                        message: "ASCII body contains invalid UTF-8".to_string(),
+                    })
+                }
+            },
+            Format::BinaryLittleEndian => BodyReader::Binary {
+                bytes: body,
+                big_endian: false,
+            },
+            Format::BinaryBigEndian => BodyReader::Binary {
+                bytes: body,
+                big_endian: true,
+            },
+        };
+
+        Self::parse_body(reader, &elements).map_err(|message| PlyError::Body {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    fn parse_body(mut reader: BodyReader, elements: &[Element]) -> Result<PlyMesh, String> {
+        let mut mesh = PlyMesh::default();
+
+        for element in elements.iter() {
+            match element.name.as_str() {
+                "vertex" => mesh.read_vertices(&mut reader, element)?,
+                "face" => mesh.read_faces(&mut reader, element)?,
+                _ => {
+                    for _ in 0..element.count {
+                        for property in element.properties.iter() {
+                            read_property(&mut reader, &property.property_type)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if mesh.positions.is_empty() || mesh.indices.is_empty() {
+            return Err("mesh doesn't contain any vertices or faces".to_string());
+        }
+        if let Some(index) = mesh
+            .indices
+            .iter()
+            .find(|index| **index as usize >= mesh.positions.len())
+        {
+            return Err(format!("face references missing vertex {}", index));
+        }
+
+        Ok(mesh)
+    }
+
+    fn read_vertices(&mut self, reader: &mut BodyReader, element: &Element) -> Result<(), String> {
+        let position = [
+            element.scalar_index(&["x"]),
+            element.scalar_index(&["y"]),
+            element.scalar_index(&["z"]),
+        ];
+        let normal = [
+            element.scalar_index(&["nx"]),
+            element.scalar_index(&["ny"]),
+            element.scalar_index(&["nz"]),
+        ];
+        let uv = [
+            element.scalar_index(&["u", "s", "texture_u", "texture_s"]),
+            element.scalar_index(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let colour = [
+            element.scalar_index(&["red", "r"]),
+            element.scalar_index(&["green", "g"]),
+            element.scalar_index(&["blue", "b"]),
+        ];
+        if position.iter().any(Option::is_none) {
+            return Err("vertices need `x`, `y` and `z` properties".to_string());
+        }
+        let has_normals = normal.iter().all(Option::is_some);
+        let has_uvs = uv.iter().all(Option::is_some);
+        let has_colours = colour.iter().all(Option::is_some);
+        let colour_scale = match colour[0].map(|i| &element.properties[i].property_type) {
+            Some(PropertyType::Scalar(scalar_type)) => scalar_type.colour_scale(),
+            _ => 1.0,
+        };
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+                *value = read_property(reader, &property.property_type)?;
+            }
+            let get = |index: Option<usize>| values[index.unwrap()];
+
+            self.positions.push(Vec3::new(
+                get(position[0]) as f32,
+                get(position[1]) as f32,
+                get(position[2]) as f32,
+            ));
+            if has_normals {
+                self.normals.push(Vec3::new(
+                    get(normal[0]) as f32,
+                    get(normal[1]) as f32,
+                    get(normal[2]) as f32,
+                ));
+            }
+            if has_uvs {
+                self.uvs
+                    .push(Vec2::new(get(uv[0]) as f32, get(uv[1]) as f32));
+            }
+            if has_colours {
+                self.colours.push(Colour::new(
+                    get(colour[0]) * colour_scale,
+                    get(colour[1]) * colour_scale,
+                    get(colour[2]) * colour_scale,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_faces(&mut self, reader: &mut BodyReader, element: &Element) -> Result<(), String> {
+        let indices_property = element
+            .properties
+            .iter()
+            .position(|property| {
+                (property.name == "vertex_indices" || property.name == "vertex_index")
+                    && matches!(property.property_type, PropertyType::List(..))
+            })
+            .ok_or("faces need a `vertex_indices` list property")?;
+
+        let mut face = Vec::new();
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match (&property.property_type, i == indices_property) {
+                    (PropertyType::List(count_type, index_type), true) => {
+                        let count = reader.read_index(*count_type)?;
+                        if count < 3 {
+                            return Err(format!("face has only {} vertices", count));
+                        }
+
+                        face.clear();
+                        for _ in 0..count {
+                            let index = reader.read_index(*index_type)?;
+                            face.push(
+                                u32::try_from(index)
+                                    .map_err(|_| format!("vertex index {} is too large", index))?,
+                            );
+                        }
+
+                        // Quads and larger polygons are triangulated as a fan
+                        for j in 1..count - 1 {
+                            self.indices
+                                .extend_from_slice(&[face[0], face[j], face[j + 1]]);
+                        }
+                    }
+                    (property_type, _) => {
+                        read_property(reader, property_type)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Uses a default diffuse material if `material_id` isn't given, coloured by the vertex colours
+    // if the mesh has them and grey otherwise
+    pub fn add_to_scene(
+        &self,
+        scene: &mut Scene,
+        object_to_world: Mat4,
+        world_to_object: Mat4,
+        material_id: Option<MaterialID>,
+        reverse_orientation: bool,
+    ) {
+        let material_id = material_id.unwrap_or_else(|| {
+            let grey = Colour::new(0.8, 0.8, 0.8);
+            let material = if self.colours.is_empty() {
+                Diffuse::new(grey)
+            } else {
+                Diffuse::textured(Arc::new(VertexColour::new(grey)))
+            };
+            scene.add_material(Box::new(material))
+        });
+
+        let triangle_mesh = Arc::new(TriangleMesh::new(
+            object_to_world,
+            world_to_object,
+            self.indices.clone(),
+            self.positions.clone(),
+            self.normals.clone(),
+            self.uvs.clone(),
+            self.colours.clone(),
+            reverse_orientation,
+        ));
+        for triangle in TriangleMesh::triangles(&triangle_mesh, material_id) {
+            scene.add_object(triangle);
+        }
+    }
+}
+
+// Returns the value of scalar properties, lists are skipped and return zero
+fn read_property(reader: &mut BodyReader, property_type: &PropertyType) -> Result<f64, String> {
+    match property_type {
+        PropertyType::Scalar(scalar_type) => reader.read(*scalar_type),
+        PropertyType::List(count_type, item_type) => {
+            let count = reader.read_index(*count_type)?;
+            for _ in 0..count {
+                reader.read(*item_type)?;
+            }
+            Ok(0.0)
+        }
+    }
+}
+
+type HeaderError = (usize, String);
+
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, &[u8]), HeaderError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let end = match data[offset..].iter().position(|byte| *byte == b'\n') {
+            Some(end) => offset + end,
+            None => return Err((line_number, "missing `end_header`".to_string())),
+        };
+        let line = std::str::from_utf8(&data[offset..end])
+            .map_err(|_| (line_number, "header contains invalid UTF-8".to_string()))?
+            .trim_end_matches('\r');
+        offset = end + 1;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err((
+                    line_number,
+                    "not a PLY file, missing `ply` magic".to_string(),
+                ));
+            }
+            continue;
+        }
+
+        let error = |message: &str| Err((line_number, message.to_string()));
+        match tokens.first().copied() {
+            Some("format") => {
+                format = match tokens.get(1).copied() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    Some("binary_big_endian") => Some(Format::BinaryBigEndian),
+                    Some(other) => {
+                        return Err((line_number, format!("unsupported format `{}`", other)))
+                    }
+                    None => return error("missing format"),
+                };
+                if tokens.get(2) != Some(&"1.0") {
+                    return error("unsupported format version, expected `1.0`");
+                }
+            }
+            Some("element") => {
+                if tokens.len() != 3 {
+                    return error("expected `element <name> <count>`");
+                }
+                let count = tokens[2]
+                    .parse()
+                    .map_err(|_| (line_number, format!("invalid count `{}`", tokens[2])))?;
+                elements.push(Element {
+                    name: tokens[1].to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            Some("property") => {
+                let element = match elements.last_mut() {
+                    Some(element) => element,
+                    None => return error("property declared before any element"),
+                };
+                let scalar = |s: &str| {
+                    ScalarType::parse(s)
+                        .ok_or_else(|| (line_number, format!("unknown property type `{}`", s)))
+                };
+
+                let property = match tokens.as_slice() {
+                    ["property", "list", count_type, item_type, name] => Property {
+                        name: name.to_string(),
+                        property_type: PropertyType::List(scalar(count_type)?, scalar(item_type)?),
+                    },
+                    ["property", scalar_type, name] => Property {
+                        name: name.to_string(),
+                        property_type: PropertyType::Scalar(scalar(scalar_type)?),
+                    },
+                    _ => return error("malformed property declaration"),
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {}
+            Some(keyword) => {
+                return Err((line_number, format!("unknown header keyword `{}`", keyword)))
+            }
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements, &data[offset..])),
+        None => Err((line_number, "missing `format` declaration".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+
+    fn parse(data: &[u8]) -> Result<PlyMesh, PlyError> {
+        PlyMesh::parse(data, Path::new("test.ply"))
+    }
+
+    fn body_error(data: &[u8]) -> String {
+        match parse(data) {
+            Ok(_) => panic!("mesh should fail to parse"),
+            Err(PlyError::Body { message, .. }) => message,
+            Err(err) => panic!("expected an error in the body, found `{}`", err),
+        }
+    }
+
+    // Unit quad with one quad face, in a binary encoding. Faces also have a list that isn't
+    // vertex indices, which should be skipped
+    fn binary_quad(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!(
+            "ply
+format {} 1.0
+element vertex 4
+property float x
+property float y
+property float z
+element face 1
+property list uchar int flags
+property list ushort uint vertex_indices
+end_header
+",
+            format
+        )
+        .into_bytes();
+
+        let mut push = |bytes: &[u8]| {
+            if big_endian {
+                data.extend(bytes.iter().rev());
+            } else {
+                data.extend_from_slice(bytes);
+            }
+        };
+        for position in POSITIONS.iter() {
+            for value in position.iter() {
+                push(&value.to_le_bytes());
+            }
+        }
+        push(&[1]);
+        push(&7i32.to_le_bytes());
+        push(&4u16.to_le_bytes());
+        for index in 0..4u32 {
+            push(&index.to_le_bytes());
+        }
+
+        data
+    }
+
+    fn assert_quad(mesh: &PlyMesh) {
+        let positions: Vec<[f32; 3]> = mesh.positions.iter().map(|p| [p.x, p.y, p.z]).collect();
+        assert_eq!(positions, POSITIONS);
+        // Triangulated as a fan around the first vertex
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn parses_ascii() {
+        let data = b"ply
+format ascii 1.0
+comment normals, UVs and 8-bit colours
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 0 0 255 0 0
+1 0 0 0 0 1 1 0 0 255 0
+1 1 0 0 0 1 1 1 0 0 255
+0 1 0 0 0 1 0 1 51 51 51
+4 0 1 2 3
+";
+        let mesh = parse(data).unwrap();
+
+        assert_quad(&mesh);
+        assert!(mesh
+            .normals
+            .iter()
+            .all(|n| [n.x, n.y, n.z] == [0.0, 0.0, 1.0]));
+        let uv = mesh.uvs[2];
+        assert_eq!((uv.x, uv.y), (1.0, 1.0));
+        let colour = mesh.colours[3];
+        assert_eq!((colour.r, colour.g, colour.b), (0.2, 0.2, 0.2));
+    }
+
+    #[test]
+    fn parses_binary() {
+        for big_endian in [false, true] {
+            let mesh = parse(&binary_quad(big_endian)).unwrap();
+
+            assert_quad(&mesh);
+            assert!(mesh.normals.is_empty() && mesh.uvs.is_empty() && mesh.colours.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated_bodies_are_errors() {
+        let data = binary_quad(false);
+        let message = body_error(&data[..data.len() - 2]);
+        assert!(message.contains("unexpected end of data"), "{}", message);
+
+        let data = b"ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+end_header
+0 0 0
+1 0 0
+";
+        let message = body_error(data);
+        assert!(message.contains("unexpected end of data"), "{}", message);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        let header = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar double vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+";
+        let message = body_error(format!("{}3 0 1 3\n", header).as_bytes());
+        assert!(message.contains("missing vertex 3"), "{}", message);
+
+        let message = body_error(format!("{}3 0 1 5000000000\n", header).as_bytes());
+        assert!(message.contains("too large"), "{}", message);
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        let data = b"ply
+format ascii 1.0
+element vertex 3
+property float x
+property quaternion y
+end_header
+";
+        match parse(data) {
+            Err(PlyError::Header { line, message, .. }) => {
+                assert_eq!(line, 5);
+                assert!(message.contains("quaternion"), "{}", message);
+            }
+            Err(err) => panic!("expected an error in the header, found `{}`", err),
+            Ok(_) => panic!("mesh should fail to parse"),
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::bsdf::{abs_cos_theta, reflect, same_hemisphere, BSDFSample};
+use crate::colour::Colour;
+use crate::intersectable::IntersectRecord;
+use crate::material::{cosine_weighted_pdf, sample_cosine_weighted, Dielectric, Material, Metal};
+use crate::texture::Texture;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use ultraviolet::Vec3;
+
+// Disney's principled parameters, all from 0 to 1 apart from `ior`
+#[derive(Copy, Clone, Debug)]
+pub struct PrincipledParameters {
+    pub base_colour: Colour,
+    pub metallic: f32,
+    pub roughness: f32,
+    // Reflectance of the dielectric specular lobe, where 0.5 is 4%
+    pub specular: f32,
+    // Tints the dielectric specular lobe towards the base colour
+    pub specular_tint: f32,
+    // Grazing retro-reflection for cloth
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    // Strength of a second, white specular lobe for lacquered surfaces
+    pub clearcoat: f32,
+    pub clearcoat_gloss: f32,
+    // Fraction of the dielectric part that refracts rather than diffusely reflects
+    pub transmission: f32,
+    // Index of refraction of the transmission lobe
+    pub ior: f32,
+    // Stretches the specular highlight along the shading frame's s axis
+    pub anisotropic: f32,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> Self {
+        PrincipledParameters {
+            base_colour: Colour::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            anisotropic: 0.0,
+        }
+    }
+}
+
+// The principled parameters as textures, so they can vary over the surface. The index of
+// refraction stays fixed, as it does for the other materials
+pub struct PrincipledTextures {
+    pub base_colour: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f32,
+    pub anisotropic: Arc<dyn Texture>,
+}
+
+impl PrincipledTextures {
+    fn evaluate(&self, rec: &IntersectRecord) -> PrincipledParameters {
+        let unit = |texture: &Arc<dyn Texture>| texture.evaluate_scalar(rec).clamp(0.0, 1.0) as f32;
+
+        PrincipledParameters {
+            base_colour: self.base_colour.evaluate(rec),
+            metallic: unit(&self.metallic),
+            roughness: unit(&self.roughness),
+            specular: unit(&self.specular),
+            specular_tint: unit(&self.specular_tint),
+            sheen: unit(&self.sheen),
+            sheen_tint: unit(&self.sheen_tint),
+            clearcoat: unit(&self.clearcoat),
+            clearcoat_gloss: unit(&self.clearcoat_gloss),
+            transmission: unit(&self.transmission),
+            ior: self.ior,
+            anisotropic: unit(&self.anisotropic),
+        }
+    }
+}
+
+// Disney's principled BSDF. Its lobes are built once for fixed parameters, or at each hit from
+// textured ones
+pub struct Principled(Parameters);
+
+enum Parameters {
+    Constant(PrincipledLobes),
+    Textured(PrincipledTextures),
+}
+
+impl Principled {
+    pub fn new(parameters: &PrincipledParameters) -> Principled {
+        Principled(Parameters::Constant(PrincipledLobes::new(parameters)))
+    }
+
+    pub fn textured(textures: PrincipledTextures) -> Principled {
+        Principled(Parameters::Textured(textures))
+    }
+
+    #[inline]
+    fn with_lobes<T>(&self, rec: &IntersectRecord, f: impl FnOnce(&PrincipledLobes) -> T) -> T {
+        match &self.0 {
+            Parameters::Constant(lobes) => f(lobes),
+            Parameters::Textured(textures) => f(&PrincipledLobes::new(&textures.evaluate(rec))),
+        }
+    }
+}
+
+impl Material for Principled {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        self.with_lobes(rec, |lobes| lobes.f(wo, wi, rec))
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        self.with_lobes(rec, |lobes| lobes.sample_f(wo, rec, rng))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        self.with_lobes(rec, |lobes| lobes.pdf(wo, wi, rec))
+    }
+}
+
+// A weighted sum of diffuse, specular, transmission and clearcoat lobes. Each lobe is sampled in
+// proportion to its weight
+struct PrincipledLobes {
+    diffuse: DisneyDiffuse,
+    specular: Metal,
+    transmission: Dielectric,
+    transmission_tint: Colour,
+    clearcoat: Clearcoat,
+    weights: [f32; 4],
+    probabilities: [f32; 4],
+}
+
+// Keeps the specular lobes rough enough to mix with the others rather than becoming delta lobes
+const MIN_ROUGHNESS: f32 = 0.03;
+
+impl PrincipledLobes {
+    fn new(parameters: &PrincipledParameters) -> PrincipledLobes {
+        let p = parameters;
+        let base_colour = p.base_colour;
+        // Hue and saturation of the base colour, for tinting
+        let luminance = base_colour.luminance();
+        let tint = if luminance > 0.0 {
+            base_colour / luminance
+        } else {
+            Colour::new(1.0, 1.0, 1.0)
+        };
+        let white = Colour::new(1.0, 1.0, 1.0);
+
+        let roughness = p.roughness.max(MIN_ROUGHNESS);
+        // Disney's aspect ratio stretches alpha, the square of roughness
+        let aspect = (1.0 - 0.9 * p.anisotropic).sqrt();
+        let (roughness_u, roughness_v) = (roughness / aspect.sqrt(), roughness * aspect.sqrt());
+
+        let dielectric_specular = white.lerp(tint, p.specular_tint as f64) * (0.08 * p.specular);
+        let specular_colour = dielectric_specular.lerp(base_colour, p.metallic as f64);
+
+        let diffuse_weight = (1.0 - p.metallic) * (1.0 - p.transmission);
+        let transmission_weight = (1.0 - p.metallic) * p.transmission;
+        // The transmission lobe reflects by its own Fresnel term, so the specular lobe only covers
+        // the rest. Both reflecting off the same part would reflect its light twice
+        let specular_weight = 1.0 - transmission_weight;
+        let clearcoat_weight = p.clearcoat;
+
+        let weights = [
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            clearcoat_weight,
+        ];
+        // The clearcoat reflects at most a few percent, so it's sampled less often than its weight
+        let probabilities = [
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            clearcoat_weight * 0.25,
+        ];
+        let total: f32 = probabilities.iter().sum();
+
+        PrincipledLobes {
+            diffuse: DisneyDiffuse {
+                base_colour,
+                roughness: p.roughness,
+                sheen: white.lerp(tint, p.sheen_tint as f64) * p.sheen,
+            },
+            specular: Metal::anisotropic(specular_colour, roughness_u, roughness_v),
+            transmission: Dielectric::rough(p.ior, roughness_u, roughness_v),
+            transmission_tint: base_colour.sqrt(),
+            clearcoat: Clearcoat {
+                alpha: 0.1 + (0.001 - 0.1) * p.clearcoat_gloss,
+            },
+            weights,
+            probabilities: [
+                probabilities[0] / total,
+                probabilities[1] / total,
+                probabilities[2] / total,
+                probabilities[3] / total,
+            ],
+        }
+    }
+
+    fn lobes(&self) -> [&dyn Material; 4] {
+        [
+            &self.diffuse,
+            &self.specular,
+            &self.transmission,
+            &self.clearcoat,
+        ]
+    }
+}
+
+impl Material for PrincipledLobes {
+    fn f(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> Colour {
+        let mut f = Colour::default();
+        for (lobe, weight) in self.lobes().iter().zip(self.weights.iter()) {
+            if *weight > 0.0 {
+                f += lobe.f(wo, wi, rec) * *weight;
+            }
+        }
+
+        if !same_hemisphere(wo, wi) {
+            // Only the transmission lobe refracts
+            f *= self.transmission_tint;
+        }
+
+        f
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        let mut u = rng.gen::<f32>();
+        let mut chosen = self.lobes().len() - 1;
+        for (i, probability) in self.probabilities.iter().enumerate() {
+            if u < *probability {
+                chosen = i;
+                break;
+            }
+            u -= probability;
+        }
+
+        let sample = self.lobes()[chosen].sample_f(wo, rec, rng)?;
+        let pdf = self.pdf(wo, &sample.wi, rec);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi: sample.wi,
+            f: self.f(wo, &sample.wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &IntersectRecord) -> f32 {
+        self.lobes()
+            .iter()
+            .zip(self.probabilities.iter())
+            .filter(|(_, probability)| **probability > 0.0)
+            .map(|(lobe, probability)| lobe.pdf(wo, wi, rec) * probability)
+            .sum()
+    }
+}
+
+#[inline]
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta.abs()).clamp(0.0, 1.0).powi(5)
+}
+
+// Diffuse lobe with darkening at grazing angles, retro-reflection on rough surfaces and sheen
+struct DisneyDiffuse {
+    base_colour: Colour,
+    roughness: f32,
+    sheen: Colour,
+}
+
+impl Material for DisneyDiffuse {
+    fn f(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> Colour {
+        let wh = *wo + *wi;
+        if !same_hemisphere(wo, wi) || wh.mag_sq() == 0.0 {
+            return Colour::default();
+        }
+        let cos_theta_d = wi.dot(wh.normalized());
+
+        let fresnel_o = schlick_weight(abs_cos_theta(wo));
+        let fresnel_i = schlick_weight(abs_cos_theta(wi));
+        let diffuse = (1.0 - fresnel_o / 2.0) * (1.0 - fresnel_i / 2.0);
+
+        let retro_roughness = 2.0 * self.roughness * cos_theta_d.powi(2);
+        let retro = retro_roughness
+            * (fresnel_o + fresnel_i + fresnel_o * fresnel_i * (retro_roughness - 1.0));
+
+        self.base_colour / PI * (diffuse + retro) + self.sheen * schlick_weight(cos_theta_d)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        sample_cosine_weighted(self, wo, rec, rng)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> f32 {
+        cosine_weighted_pdf(wo, wi)
+    }
+}
+
+// White specular layer with a Generalized-Trowbridge-Reitz (GTR1) distribution, whose long tail
+// gives the soft halo of lacquer. Fixed at an index of refraction of 1.5
+struct Clearcoat {
+    alpha: f32,
+}
+
+impl Clearcoat {
+    fn d(&self, cos_theta_h: f32) -> f32 {
+        let alpha2 = self.alpha * self.alpha;
+        (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta_h.powi(2)))
+    }
+
+    // Separable Smith masking with a fixed roughness, including the 1 / cos terms of the BRDF
+    fn smith_g(cos_theta: f32) -> f32 {
+        let alpha2 = 0.25 * 0.25;
+        let cos2_theta = cos_theta * cos_theta;
+        1.0 / (cos_theta + (alpha2 + cos2_theta - alpha2 * cos2_theta).sqrt())
+    }
+}
+
+impl Material for Clearcoat {
+    fn f(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> Colour {
+        let wh = *wo + *wi;
+        if !same_hemisphere(wo, wi) || wh.mag_sq() == 0.0 {
+            return Colour::default();
+        }
+        let wh = wh.normalized();
+
+        let d = self.d(abs_cos_theta(&wh));
+        let f = 0.04 + 0.96 * schlick_weight(wo.dot(wh));
+        let g = Clearcoat::smith_g(abs_cos_theta(wo)) * Clearcoat::smith_g(abs_cos_theta(wi));
+
+        Colour::new(1.0, 1.0, 1.0) * (d * f * g / 4.0)
+    }
+
+    fn sample_f(&self, wo: &Vec3, rec: &IntersectRecord, rng: &mut StdRng) -> Option<BSDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let alpha2 = self.alpha * self.alpha;
+        let u: f32 = rng.gen();
+        let cos_theta = ((1.0 - alpha2.powf(1.0 - u)) / (1.0 - alpha2))
+            .max(0.0)
+            .sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let mut wh = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        if !same_hemisphere(wo, &wh) {
+            wh = -wh;
+        }
+
+        let wi = reflect(wo, &wh);
+        let pdf = self.pdf(wo, &wi, rec);
+        if !same_hemisphere(wo, &wi) || pdf == 0.0 {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi,
+            f: self.f(wo, &wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _rec: &IntersectRecord) -> f32 {
+        let wh = *wo + *wi;
+        if !same_hemisphere(wo, wi) || wh.mag_sq() == 0.0 {
+            return 0.0;
+        }
+        let wh = wh.normalized();
+
+        self.d(abs_cos_theta(&wh)) * abs_cos_theta(&wh) / (4.0 * wo.dot(wh).abs())
+    }
+}
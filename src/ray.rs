@@ -7,6 +7,16 @@ pub struct Ray {
     pub direction: Vec3,
     pub t_min: f32,
     pub t_max: f32,
+    // Rays through the neighbouring pixels, for estimating how much of a surface a ray covers
+    pub differential: Option<RayDifferential>,
+}
+
+#[derive(Copy, Clone)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -16,12 +26,26 @@ impl Ray {
             direction,
             t_min,
             t_max,
+            differential: None,
         }
     }
 
     pub fn at(&self, distance: f32) -> Vec3 {
         self.origin + self.direction * distance
     }
+
+    // Moves the offset rays closer by `scale`, as each of many samples per pixel only has to
+    // cover a fraction of the pixel
+    pub fn scale_differential(&mut self, scale: f32) {
+        if let Some(differential) = &mut self.differential {
+            differential.rx_origin = self.origin + (differential.rx_origin - self.origin) * scale;
+            differential.ry_origin = self.origin + (differential.ry_origin - self.origin) * scale;
+            differential.rx_direction =
+                self.direction + (differential.rx_direction - self.direction) * scale;
+            differential.ry_direction =
+                self.direction + (differential.ry_direction - self.direction) * scale;
+        }
+    }
 }
 
 impl Mul<&Ray> for Mat4 {
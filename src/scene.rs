@@ -1,4 +1,5 @@
 use crate::bvh::BVHNode;
+use crate::colour::Colour;
 use crate::intersectable::{IntersectRecord, Intersectable};
 use crate::material::{Material, MaterialID, MaterialStore};
 use crate::ray::Ray;
@@ -9,8 +10,11 @@ use ultraviolet::Vec3;
 pub struct Scene {
     pub objects: Vec<Box<dyn Shape>>,
     pub bvh: Option<BVHNode>,
-    pub light_positions: Vec<Vec3>,
+    // Indices of the objects with an emissive material, found when the BVH is generated
+    pub lights: Vec<usize>,
     pub materials: MaterialStore,
+    // Radiance seen by rays that escape the scene
+    pub background: Colour,
 }
 
 impl Scene {
@@ -18,25 +22,54 @@ impl Scene {
         self.objects.push(object);
     }
 
-    pub fn add_light_pos(&mut self, light_pos: Vec3) {
-        self.light_positions.push(light_pos);
-    }
-
     pub fn add_material(&mut self, material: Box<dyn Material>) -> MaterialID {
         self.materials.add(material)
     }
 
+    // Intersects the scene, also returning the index of the object that was hit
+    pub fn intersect_object(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(IntersectRecord, f32, usize)> {
+        if let Some(bvh) = &self.bvh {
+            bvh.intersect(&self.objects, ray, test_alpha_texture)
+        } else {
+            panic!("Forgotten to generate BVH structure for scene")
+        }
+    }
+
+    // Probability of light sampling choosing `wi` from `rec` through the light `object`
+    pub fn light_pdf(&self, object: usize, rec: &IntersectRecord, wi: &Vec3) -> f32 {
+        if self.lights.binary_search(&object).is_err() {
+            return 0.0;
+        }
+
+        self.objects[object].pdf_wi(rec, wi) / self.lights.len() as f32
+    }
+
     pub fn generate_bvh(&mut self) {
         self.bvh = Some(BVHNode::construct(&mut self.objects));
+
+        // Building the BVH reorders the objects, so lights are only looked up afterwards
+        let materials = &self.materials;
+        self.lights = self
+            .objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| {
+                materials
+                    .get(object.material_id())
+                    .is_some_and(|material| material.is_emissive())
+            })
+            .map(|(index, _)| index)
+            .collect();
     }
 }
 
 impl Intersectable for Scene {
     fn intersect(&self, ray: &Ray, test_alpha_texture: bool) -> Option<(IntersectRecord, f32)> {
-        if let Some(bvh) = &self.bvh {
-            bvh.intersect(&self.objects, ray, test_alpha_texture)
-        } else {
-            panic!("Forgotten to generate BVH structure for scene")
-        }
+        self.intersect_object(ray, test_alpha_texture)
+            .map(|(rec, t_hit, _)| (rec, t_hit))
     }
 }
@@ -0,0 +1,1815 @@
+use crate::bump::NormalModifier;
+use crate::camera::Camera;
+use crate::colour::Colour;
+use crate::cylinder::Cylinder;
+use crate::material::{
+    conductor_preset, Dielectric, Diffuse, Emissive, Material, MaterialID, Metal, OrenNayar,
+    Plastic, Roughness, Subsurface, ThinDielectric, CONDUCTOR_PRESETS,
+};
+use crate::obj::ObjModel;
+use crate::ply::PlyMesh;
+use crate::principled::{Principled, PrincipledParameters, PrincipledTextures};
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::texture::{
+    Cellular, Checkerboard, ConstantTexture, FilterMode, ImageTexture, Marble, Noise, Texture,
+    TextureMapping, TextureSpace, VertexColour, WrapMode,
+};
+use crate::transform;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ultraviolet::{Mat4, Vec3};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(String),
+    UnsupportedFormat(String),
+    // Scene that parsed but doesn't make sense, with the line and column of the offending value
+    // when the source format records them
+    Invalid {
+        field: String,
+        message: String,
+        location: Option<(usize, usize)>,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse(message) => write!(f, "{}", message),
+            SceneError::UnsupportedFormat(extension) => write!(
+                f,
+                "unsupported scene format `{}`, expected `toml`, `json` or `pbrt`",
+                extension
+            ),
+            SceneError::Invalid {
+                field,
+                message,
+                location: None,
+            } => write!(f, "`{}`: {}", field, message),
+            SceneError::Invalid {
+                field,
+                message,
+                location: Some((line, column)),
+            } => write!(
+                f,
+                "`{}`: {} at line {} column {}",
+                field, message, line, column
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(err: std::io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDescription>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub shapes: Vec<ShapeDescription>,
+    // Colour of the uniform environment around the scene, black if not set
+    pub background: Option<[f64; 3]>,
+
+    // Directory that relative file paths in the description are resolved against
+    #[serde(skip)]
+    pub directory: PathBuf,
+    // Where values were in the source, for TOML scenes
+    #[serde(skip)]
+    source_map: Option<SourceMap>,
+}
+
+// Positions of the values in a TOML scene, so errors found while building it can point at the
+// offending value. Tables written as `[headers]` aren't given positions by the parser, so they're
+// located by their first value instead
+struct SourceMap {
+    root: SourceNode,
+    // Byte offset of the start of each line
+    line_starts: Vec<usize>,
+}
+
+struct SourceNode {
+    start: Option<usize>,
+    children: SourceChildren,
+}
+
+enum SourceChildren {
+    Value,
+    Table(BTreeMap<String, SourceNode>),
+    Array(Vec<SourceNode>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub origin: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "default_up")]
+    pub up: [f32; 3],
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    pub focus_distance: Option<f32>,
+    #[serde(default = "default_t_min")]
+    pub t_min: f32,
+    pub t_max: Option<f32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderDescription {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MaterialDescription {
+    // Lambertian, or Oren-Nayar rough diffuse with a `sigma` facet angle in degrees
+    Diffuse {
+        albedo: ColourDescription,
+        #[serde(default)]
+        sigma: f32,
+    },
+    Emissive {
+        albedo: ColourDescription,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    // A perfect mirror with the default `roughness` of zero. Setting `roughness_v` makes the
+    // highlight anisotropic, with `roughness` along the other tangent
+    Metal {
+        albedo: ColourDescription,
+        #[serde(default = "default_roughness")]
+        roughness: ScalarDescription,
+        roughness_v: Option<ScalarDescription>,
+    },
+    // Physically based metal, either one of the named presets or a complex index of refraction
+    // `eta + ik` per channel
+    Conductor {
+        preset: Option<String>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default = "default_roughness")]
+        roughness: ScalarDescription,
+        roughness_v: Option<ScalarDescription>,
+    },
+    // Diffuse base under a glossy dielectric coating
+    Plastic {
+        albedo: ColourDescription,
+        #[serde(default = "default_ior")]
+        ior: f32,
+        #[serde(default = "default_roughness")]
+        roughness: ScalarDescription,
+    },
+    Principled(PrincipledDescription),
+    // Blends two other materials by name, where `amount` is the fraction of the second
+    Mix {
+        materials: [String; 2],
+        #[serde(default = "default_amount")]
+        amount: ScalarDescription,
+    },
+    // Shades back faces as front faces of the `back` material, or of `front` if it isn't set
+    TwoSided {
+        front: String,
+        back: Option<String>,
+    },
+    // Shades another material as if its surface were displaced along the normal by the scalar
+    // `height` texture, in scene units times `scale`
+    Bump {
+        material: String,
+        height: String,
+        #[serde(default = "default_bump_scale")]
+        scale: f32,
+    },
+    // Shades another material with normals from a tangent space normal map, which should be
+    // loaded with `srgb` off
+    NormalMap {
+        material: String,
+        texture: String,
+    },
+    // Window panes and bubbles, reflecting and transmitting without bending light
+    ThinDielectric {
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+    // Smooth dielectric boundary around a scattering medium, where light travels an average of
+    // `mean_free_path` per channel between events and `albedo` of it survives each one
+    Subsurface {
+        albedo: ColourDescription,
+        mean_free_path: [f64; 3],
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+    Dielectric {
+        ior: f32,
+        #[serde(default = "default_roughness")]
+        roughness: ScalarDescription,
+        roughness_v: Option<ScalarDescription>,
+    },
+}
+
+// Textures are referenced by name from material fields in place of a constant colour or number.
+// Procedural textures are evaluated at the hit's UVs or position in `space`, placed there by
+// `transform` like a shape, and blend between two `colours`. The noisy ones are reproducible for a
+// given `seed`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TextureDescription {
+    // Image mapped over the unit square of UVs and filtered over each hit's footprint by `filter`.
+    // `srgb` decodes the usual encoding of images to linear colours, and should be turned off for
+    // images holding data
+    Image {
+        file: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+        #[serde(default)]
+        filter: FilterMode,
+        #[serde(default = "default_srgb")]
+        srgb: bool,
+    },
+    // Alternating colours on a unit grid
+    Checker {
+        colours: [[f64; 3]; 2],
+        #[serde(default = "default_uv_space")]
+        space: TextureSpace,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+    },
+    // Fractal Perlin noise, adding `octaves` of finer noise each `roughness` times as strong as
+    // the last. `turbulence` folds the noise into creases
+    Noise {
+        colours: [[f64; 3]; 2],
+        #[serde(default = "default_object_space")]
+        space: TextureSpace,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_noise_roughness")]
+        roughness: f32,
+        #[serde(default)]
+        turbulence: bool,
+    },
+    // Veins of the second colour running across y, bent by noise scaled by `variation`
+    Marble {
+        colours: [[f64; 3]; 2],
+        #[serde(default = "default_object_space")]
+        space: TextureSpace,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_noise_roughness")]
+        roughness: f32,
+        #[serde(default = "default_variation")]
+        variation: f32,
+    },
+    // Colour interpolated from the vertices of meshes that have them, and `fallback` elsewhere
+    VertexColour {
+        #[serde(default = "default_fallback_colour")]
+        fallback: [f64; 3],
+    },
+    // Worley cells, from the first colour at their centres to the second a unit away
+    Cellular {
+        colours: [[f64; 3]; 2],
+        #[serde(default = "default_object_space")]
+        space: TextureSpace,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+// Either a constant colour or the name of a texture
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ColourDescription {
+    Constant([f64; 3]),
+    Texture(String),
+}
+
+// Either a constant number or the name of a texture, using the average of its channels
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ScalarDescription {
+    Constant(f32),
+    Texture(String),
+}
+
+// Disney's principled parameters, where anything not set keeps its default. All but `ior` can be
+// textures
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledDescription {
+    pub base_colour: Option<ColourDescription>,
+    pub metallic: Option<ScalarDescription>,
+    pub roughness: Option<ScalarDescription>,
+    pub specular: Option<ScalarDescription>,
+    pub specular_tint: Option<ScalarDescription>,
+    pub sheen: Option<ScalarDescription>,
+    pub sheen_tint: Option<ScalarDescription>,
+    pub clearcoat: Option<ScalarDescription>,
+    pub clearcoat_gloss: Option<ScalarDescription>,
+    pub transmission: Option<ScalarDescription>,
+    pub ior: Option<f32>,
+    pub anisotropic: Option<ScalarDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ShapeDescription {
+    // Sphere with `transform` applied around its own centre before it's moved to `centre`
+    Sphere {
+        #[serde(default)]
+        centre: [f32; 3],
+        radius: f32,
+        material: String,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        reverse_orientation: bool,
+    },
+    Cylinder {
+        radius: f32,
+        length: f32,
+        material: String,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        reverse_orientation: bool,
+    },
+    // Triangle mesh loaded from a file, using the file's own materials if `material` isn't set
+    Mesh {
+        file: PathBuf,
+        material: Option<String>,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        #[serde(default)]
+        reverse_orientation: bool,
+    },
+}
+
+// Transforms are composed in the order they're listed, so the last entry is applied first
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum TransformDescription {
+    Translate([f32; 3]),
+    Rotate { angle: f32, axis: [f32; 3] },
+    Scale([f32; 3]),
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn default_amount() -> ScalarDescription {
+    ScalarDescription::Constant(0.5)
+}
+
+fn default_roughness() -> ScalarDescription {
+    ScalarDescription::Constant(0.0)
+}
+
+fn default_bump_scale() -> f32 {
+    1.0
+}
+
+fn default_fallback_colour() -> [f64; 3] {
+    [0.8, 0.8, 0.8]
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_fov() -> f32 {
+    50.0
+}
+
+fn default_t_min() -> f32 {
+    0.001
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_srgb() -> bool {
+    true
+}
+
+fn default_uv_space() -> TextureSpace {
+    TextureSpace::Uv
+}
+
+fn default_object_space() -> TextureSpace {
+    TextureSpace::Object
+}
+
+fn default_octaves() -> u32 {
+    8
+}
+
+fn default_noise_roughness() -> f32 {
+    0.5
+}
+
+fn default_variation() -> f32 {
+    1.0
+}
+
+impl SceneDescription {
+    pub fn from_file(path: &Path) -> Result<SceneDescription, SceneError> {
+        let contents = std::fs::read_to_string(path)?;
+
+        let mut description = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("json") => Self::from_json(&contents),
+            extension => Err(SceneError::UnsupportedFormat(
+                extension.unwrap_or_default().to_string(),
+            )),
+        }?;
+        description.directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+        Ok(description)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<SceneDescription, SceneError> {
+        let mut description: SceneDescription =
+            toml::from_str(contents).map_err(|err| SceneError::Parse(err.to_string()))?;
+        description.source_map = SourceMap::new(contents);
+
+        Ok(description)
+    }
+
+    pub fn from_json(contents: &str) -> Result<SceneDescription, SceneError> {
+        serde_json::from_str(contents).map_err(|err| SceneError::Parse(err.to_string()))
+    }
+
+    pub fn build(&self, aspect_ratio: f32) -> Result<(Scene, Camera), SceneError> {
+        self.build_scene(aspect_ratio).map_err(|err| match err {
+            SceneError::Invalid {
+                field,
+                message,
+                location: None,
+            } => {
+                let location = self
+                    .source_map
+                    .as_ref()
+                    .and_then(|source_map| source_map.locate(&field));
+                SceneError::Invalid {
+                    field,
+                    message,
+                    location,
+                }
+            }
+            err => err,
+        })
+    }
+
+    fn build_scene(&self, aspect_ratio: f32) -> Result<(Scene, Camera), SceneError> {
+        let mut scene = Scene::default();
+        if let Some(background) = &self.background {
+            scene.background = check_colour("background", background)?;
+        }
+
+        let mut textures = HashMap::new();
+        for (name, texture) in self.textures.iter() {
+            let field = format!("textures.{}", name);
+            textures.insert(name.as_str(), texture.build(&field, &self.directory)?);
+        }
+
+        let mut material_ids = HashMap::new();
+        for name in self.materials.keys() {
+            self.build_material(
+                name,
+                &mut Vec::new(),
+                &textures,
+                &mut material_ids,
+                &mut scene,
+            )?;
+        }
+
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let field = format!("shapes[{}]", i);
+            shape.build(&field, &self.directory, &material_ids, &mut scene)?;
+        }
+
+        if scene.objects.is_empty() {
+            return invalid("shapes", "scene doesn't contain any shapes");
+        }
+        scene.generate_bvh();
+
+        let camera = self.camera.build(aspect_ratio)?;
+
+        Ok((scene, camera))
+    }
+
+    // Why a material can't be part of a mix, if it can't. Only called on materials that have been
+    // built, so there are no cycles to follow
+    fn unmixable(&self, name: &str) -> Option<&'static str> {
+        let material = &self.materials[name];
+        match material {
+            MaterialDescription::Bump { .. } | MaterialDescription::NormalMap { .. } => {
+                Some("is bump or normal mapped, which should wrap the mix instead")
+            }
+            MaterialDescription::Subsurface { .. } => Some("scatters light inside the shape"),
+            _ => material
+                .references()
+                .into_iter()
+                .find_map(|(_, reference)| self.unmixable(reference)),
+        }
+    }
+
+    // Whether light refracted through a material into the shape scatters through a medium there
+    fn scatters_inside(&self, name: &str) -> bool {
+        let material = &self.materials[name];
+        match material {
+            MaterialDescription::Subsurface { .. } => true,
+            MaterialDescription::TwoSided { front, .. } => self.scatters_inside(front),
+            _ => material
+                .references()
+                .into_iter()
+                .any(|(_, reference)| self.scatters_inside(reference)),
+        }
+    }
+
+    // Builds a material after any it references. `path` holds the materials being built that lead
+    // to this one, so a material that ends up referencing itself is reported instead of recursing
+    // forever
+    fn build_material<'a>(
+        &'a self,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+        material_ids: &mut HashMap<&'a str, MaterialID>,
+        scene: &mut Scene,
+    ) -> Result<MaterialID, SceneError> {
+        if let Some(material_id) = material_ids.get(name) {
+            return Ok(*material_id);
+        }
+        let field = format!("materials.{}", name);
+        if path.contains(&name) {
+            path.push(name);
+            return invalid(
+                &field,
+                &format!(
+                    "material references itself through `{}`",
+                    path.join("` -> `")
+                ),
+            );
+        }
+
+        let material = &self.materials[name];
+        path.push(name);
+        let mut referenced = Vec::new();
+        for (reference_field, reference) in material.references() {
+            if !self.materials.contains_key(reference) {
+                return invalid(
+                    &format!("{}.{}", field, reference_field),
+                    &format!("unknown material `{}`", reference),
+                );
+            }
+            referenced.push(self.build_material(reference, path, textures, material_ids, scene)?);
+        }
+        path.pop();
+
+        // Mixes choose a material per direction, after the hit has been perturbed and entered,
+        // so they can't pass either on
+        if let MaterialDescription::Mix { materials, .. } = material {
+            for reference in materials {
+                if let Some(reason) = self.unmixable(reference) {
+                    return invalid(
+                        &format!("{}.materials", field),
+                        &format!("material `{}` can't be mixed, as it {}", reference, reason),
+                    );
+                }
+            }
+        }
+        // Back faces are hit from inside the shape, so light refracted through them would leave it
+        if let MaterialDescription::TwoSided {
+            back: Some(back), ..
+        } = material
+        {
+            if self.scatters_inside(back) {
+                return invalid(
+                    &format!("{}.back", field),
+                    &format!(
+                        "material `{}` scatters light inside the shape, which only the front of a \
+                         two-sided material can lead into",
+                        back
+                    ),
+                );
+            }
+        }
+
+        // The referenced materials were all just added, so these can't fail
+        let material_id = match material {
+            MaterialDescription::Mix { amount, .. } => {
+                let amount_field = format!("{}.amount", field);
+                match amount {
+                    ScalarDescription::Constant(amount) => {
+                        check_unit(&amount_field, *amount)?;
+                        scene
+                            .materials
+                            .add_mix(referenced[0], referenced[1], *amount)
+                            .unwrap()
+                    }
+                    ScalarDescription::Texture(name) => {
+                        let amount = lookup_texture(&amount_field, name, textures)?;
+                        scene
+                            .materials
+                            .add_textured_mix(referenced[0], referenced[1], amount)
+                            .unwrap()
+                    }
+                }
+            }
+            MaterialDescription::TwoSided { .. } => scene
+                .materials
+                .add_two_sided(referenced[0], referenced[1])
+                .unwrap(),
+            MaterialDescription::Bump { height, scale, .. } => {
+                let height = lookup_texture(&format!("{}.height", field), height, textures)?;
+                if !scale.is_finite() {
+                    return invalid(&format!("{}.scale", field), "scale must be finite");
+                }
+                let modifier = NormalModifier::Bump {
+                    height,
+                    scale: *scale,
+                };
+                scene
+                    .materials
+                    .add_perturbed(referenced[0], modifier)
+                    .unwrap()
+            }
+            MaterialDescription::NormalMap { texture, .. } => {
+                let texture = lookup_texture(&format!("{}.texture", field), texture, textures)?;
+                scene
+                    .materials
+                    .add_perturbed(referenced[0], NormalModifier::NormalMap(texture))
+                    .unwrap()
+            }
+            material => scene.add_material(material.build(&field, textures)?),
+        };
+        material_ids.insert(name, material_id);
+
+        Ok(material_id)
+    }
+}
+
+impl CameraDescription {
+    fn build(&self, aspect_ratio: f32) -> Result<Camera, SceneError> {
+        let origin = Vec3::from(self.origin);
+        let target = Vec3::from(self.target);
+        let up = Vec3::from(self.up);
+        let view = target - origin;
+
+        if view.mag_sq() == 0.0 {
+            return invalid("camera.target", "target must differ from the camera origin");
+        }
+        if up.cross(view).mag_sq() == 0.0 {
+            return invalid("camera.up", "up must not be parallel to the view direction");
+        }
+        if self.fov.is_nan() || self.fov <= 0.0 || self.fov >= 180.0 {
+            return invalid("camera.fov", "fov must be between 0 and 180 degrees");
+        }
+        check_non_negative("camera.aperture", self.aperture)?;
+
+        let focus_distance = self.focus_distance.unwrap_or_else(|| view.mag());
+        check_positive("camera.focus_distance", focus_distance)?;
+
+        let t_max = self.t_max.unwrap_or(f32::INFINITY);
+        check_non_negative("camera.t_min", self.t_min)?;
+        if t_max.is_nan() || t_max <= self.t_min {
+            return invalid("camera.t_max", "t_max must be greater than t_min");
+        }
+
+        Ok(Camera::new(
+            origin,
+            target,
+            up,
+            self.fov,
+            aspect_ratio,
+            self.aperture,
+            focus_distance,
+            self.t_min,
+            t_max,
+        ))
+    }
+}
+
+impl MaterialDescription {
+    // Names of the other materials this one is built from, with the fields they're given in
+    fn references(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            MaterialDescription::Mix { materials, .. } => {
+                vec![("materials", &materials[0]), ("materials", &materials[1])]
+            }
+            MaterialDescription::TwoSided { front, back } => {
+                vec![("front", front), ("back", back.as_ref().unwrap_or(front))]
+            }
+            MaterialDescription::Bump { material, .. }
+            | MaterialDescription::NormalMap { material, .. } => vec![("material", material)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn build(
+        &self,
+        field: &str,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+    ) -> Result<Box<dyn Material>, SceneError> {
+        Ok(match self {
+            MaterialDescription::Diffuse { albedo, sigma } => {
+                let albedo = albedo.build(&format!("{}.albedo", field), textures)?;
+                if !(0.0..=90.0).contains(sigma) {
+                    return invalid(
+                        &format!("{}.sigma", field),
+                        &format!("expected an angle from 0 to 90 degrees, found {}", sigma),
+                    );
+                }
+                if *sigma > 0.0 {
+                    Box::new(OrenNayar::textured(albedo, *sigma))
+                } else {
+                    Box::new(Diffuse::textured(albedo))
+                }
+            }
+            MaterialDescription::Emissive { albedo, intensity } => {
+                let albedo = albedo.build(&format!("{}.albedo", field), textures)?;
+                check_non_negative(&format!("{}.intensity", field), *intensity)?;
+                Box::new(Emissive::textured(albedo, *intensity))
+            }
+            MaterialDescription::Metal {
+                albedo,
+                roughness,
+                roughness_v,
+            } => {
+                let albedo = albedo.build(&format!("{}.albedo", field), textures)?;
+                let roughness = build_roughness(field, roughness, roughness_v, textures)?;
+                Box::new(Metal::textured(albedo, roughness))
+            }
+            MaterialDescription::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+                roughness_v,
+            } => {
+                let (eta, k) = match (preset, eta, k) {
+                    (Some(preset), None, None) => conductor_preset(preset).ok_or_else(|| {
+                        let presets: Vec<_> =
+                            CONDUCTOR_PRESETS.iter().map(|(name, _, _)| *name).collect();
+                        invalid_error(
+                            &format!("{}.preset", field),
+                            &format!(
+                                "unknown preset `{}`, expected one of {}",
+                                preset,
+                                presets.join(", ")
+                            ),
+                        )
+                    })?,
+                    (None, Some(eta), Some(k)) => (
+                        check_colour(&format!("{}.eta", field), eta)?,
+                        check_colour(&format!("{}.k", field), k)?,
+                    ),
+                    _ => return invalid(field, "expected either a `preset` or both `eta` and `k`"),
+                };
+                let roughness = build_roughness(field, roughness, roughness_v, textures)?;
+                Box::new(Metal::textured_conductor(eta, k, roughness))
+            }
+            MaterialDescription::Plastic {
+                albedo,
+                ior,
+                roughness,
+            } => {
+                let albedo_field = format!("{}.albedo", field);
+                let albedo_texture = albedo.build(&albedo_field, textures)?;
+                if albedo.is_over_one() {
+                    return invalid(&albedo_field, "plastic albedo can't be more than 1");
+                }
+                check_positive(&format!("{}.ior", field), *ior)?;
+                let roughness = build_roughness(field, roughness, &None, textures)?;
+                Box::new(Plastic::textured(albedo_texture, *ior, roughness))
+            }
+            MaterialDescription::Principled(description) => {
+                Box::new(description.build(field, textures)?)
+            }
+            MaterialDescription::ThinDielectric { ior } => {
+                check_positive(&format!("{}.ior", field), *ior)?;
+                Box::new(ThinDielectric::new(*ior))
+            }
+            MaterialDescription::Subsurface {
+                albedo,
+                mean_free_path,
+                ior,
+            } => {
+                let albedo_field = format!("{}.albedo", field);
+                let albedo_texture = albedo.build(&albedo_field, textures)?;
+                if albedo.is_over_one() {
+                    return invalid(&albedo_field, "subsurface albedo can't be more than 1");
+                }
+                if !mean_free_path.iter().all(|d| *d > 0.0 && d.is_finite()) {
+                    return invalid(
+                        &format!("{}.mean_free_path", field),
+                        "mean free path components must be positive",
+                    );
+                }
+                let mean_free_path =
+                    Colour::new(mean_free_path[0], mean_free_path[1], mean_free_path[2]);
+                check_positive(&format!("{}.ior", field), *ior)?;
+                Box::new(Subsurface::textured(albedo_texture, mean_free_path, *ior))
+            }
+            MaterialDescription::Mix { .. }
+            | MaterialDescription::TwoSided { .. }
+            | MaterialDescription::Bump { .. }
+            | MaterialDescription::NormalMap { .. } => {
+                unreachable!("materials referencing others are built by the scene")
+            }
+            MaterialDescription::Dielectric {
+                ior,
+                roughness,
+                roughness_v,
+            } => {
+                check_positive(&format!("{}.ior", field), *ior)?;
+                let roughness = build_roughness(field, roughness, roughness_v, textures)?;
+                Box::new(Dielectric::textured(*ior, roughness))
+            }
+        })
+    }
+}
+
+impl TextureDescription {
+    fn build(&self, field: &str, directory: &Path) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match self {
+            TextureDescription::Image {
+                file,
+                wrap,
+                filter,
+                srgb,
+            } => {
+                let texture = ImageTexture::load(&directory.join(file), *wrap, *filter, *srgb)
+                    .map_err(|err| invalid_error(&format!("{}.file", field), &err.to_string()))?;
+                Arc::new(texture)
+            }
+            TextureDescription::Checker {
+                colours,
+                space,
+                transform,
+            } => Arc::new(Checkerboard::new(
+                build_mapping(field, *space, transform)?,
+                check_colours(field, colours)?,
+            )),
+            TextureDescription::Noise {
+                colours,
+                space,
+                transform,
+                seed,
+                octaves,
+                roughness,
+                turbulence,
+            } => Arc::new(Noise::new(
+                build_mapping(field, *space, transform)?,
+                check_colours(field, colours)?,
+                *seed,
+                check_octaves(field, *octaves)?,
+                check_unit(&format!("{}.roughness", field), *roughness)?,
+                *turbulence,
+            )),
+            TextureDescription::Marble {
+                colours,
+                space,
+                transform,
+                seed,
+                octaves,
+                roughness,
+                variation,
+            } => Arc::new(Marble::new(
+                build_mapping(field, *space, transform)?,
+                check_colours(field, colours)?,
+                *seed,
+                check_octaves(field, *octaves)?,
+                check_unit(&format!("{}.roughness", field), *roughness)?,
+                check_non_negative(&format!("{}.variation", field), *variation)?,
+            )),
+            TextureDescription::Cellular {
+                colours,
+                space,
+                transform,
+                seed,
+            } => Arc::new(Cellular::new(
+                build_mapping(field, *space, transform)?,
+                check_colours(field, colours)?,
+                *seed,
+            )),
+            TextureDescription::VertexColour { fallback } => Arc::new(VertexColour::new(
+                check_colour(&format!("{}.fallback", field), fallback)?,
+            )),
+        })
+    }
+}
+
+fn build_mapping(
+    field: &str,
+    space: TextureSpace,
+    transform: &[TransformDescription],
+) -> Result<TextureMapping, SceneError> {
+    let (_, to_texture) = build_transform(&format!("{}.transform", field), transform)?;
+
+    Ok(TextureMapping::new(space, to_texture))
+}
+
+fn check_colours(field: &str, colours: &[[f64; 3]; 2]) -> Result<[Colour; 2], SceneError> {
+    let field = format!("{}.colours", field);
+
+    Ok([
+        check_colour(&field, &colours[0])?,
+        check_colour(&field, &colours[1])?,
+    ])
+}
+
+// Octaves past the precision of the noise only cost time
+fn check_octaves(field: &str, octaves: u32) -> Result<u32, SceneError> {
+    if (1..=16).contains(&octaves) {
+        Ok(octaves)
+    } else {
+        invalid(
+            &format!("{}.octaves", field),
+            &format!("expected from 1 to 16 octaves, found {}", octaves),
+        )
+    }
+}
+
+impl ColourDescription {
+    fn build(
+        &self,
+        field: &str,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match self {
+            ColourDescription::Constant(colour) => {
+                Ok(Arc::new(ConstantTexture::new(check_colour(field, colour)?)))
+            }
+            ColourDescription::Texture(name) => lookup_texture(field, name, textures),
+        }
+    }
+
+    // Whether it's a constant with a component over 1. Textures are clamped where they're used
+    fn is_over_one(&self) -> bool {
+        match self {
+            ColourDescription::Constant(colour) => colour.iter().any(|c| *c > 1.0),
+            ColourDescription::Texture(_) => false,
+        }
+    }
+}
+
+impl ScalarDescription {
+    // For parameters from 0 to 1. Textures are clamped where they're used
+    fn build_unit(
+        &self,
+        field: &str,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match self {
+            ScalarDescription::Constant(value) => Ok(Arc::new(ConstantTexture::scalar(
+                check_unit(field, *value)?,
+            ))),
+            ScalarDescription::Texture(name) => lookup_texture(field, name, textures),
+        }
+    }
+}
+
+impl PrincipledDescription {
+    fn build(
+        &self,
+        field: &str,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+    ) -> Result<Principled, SceneError> {
+        let mut parameters = PrincipledParameters::default();
+        let mut textured = false;
+        match &self.base_colour {
+            Some(ColourDescription::Constant(base_colour)) => {
+                parameters.base_colour =
+                    check_colour(&format!("{}.base_colour", field), base_colour)?;
+            }
+            Some(ColourDescription::Texture(_)) => textured = true,
+            None => {}
+        }
+        if let Some(ior) = self.ior {
+            parameters.ior = check_positive(&format!("{}.ior", field), ior)?;
+        }
+
+        let unit_parameters = [
+            (&self.metallic, "metallic", &mut parameters.metallic),
+            (&self.roughness, "roughness", &mut parameters.roughness),
+            (&self.specular, "specular", &mut parameters.specular),
+            (
+                &self.specular_tint,
+                "specular_tint",
+                &mut parameters.specular_tint,
+            ),
+            (&self.sheen, "sheen", &mut parameters.sheen),
+            (&self.sheen_tint, "sheen_tint", &mut parameters.sheen_tint),
+            (&self.clearcoat, "clearcoat", &mut parameters.clearcoat),
+            (
+                &self.clearcoat_gloss,
+                "clearcoat_gloss",
+                &mut parameters.clearcoat_gloss,
+            ),
+            (
+                &self.transmission,
+                "transmission",
+                &mut parameters.transmission,
+            ),
+            (
+                &self.anisotropic,
+                "anisotropic",
+                &mut parameters.anisotropic,
+            ),
+        ];
+        for (value, name, parameter) in unit_parameters {
+            match value {
+                Some(ScalarDescription::Constant(value)) => {
+                    *parameter = check_unit(&format!("{}.{}", field, name), *value)?;
+                }
+                Some(ScalarDescription::Texture(_)) => textured = true,
+                None => {}
+            }
+        }
+        if !textured {
+            return Ok(Principled::new(&parameters));
+        }
+
+        // Parameters that aren't textured keep the values found above
+        let scalar =
+            |description: &Option<ScalarDescription>, name: &str, value: f32| match description {
+                Some(ScalarDescription::Texture(texture)) => {
+                    lookup_texture(&format!("{}.{}", field, name), texture, textures)
+                }
+                _ => Ok(Arc::new(ConstantTexture::scalar(value)) as Arc<dyn Texture>),
+            };
+        let base_colour = match &self.base_colour {
+            Some(ColourDescription::Texture(name)) => {
+                lookup_texture(&format!("{}.base_colour", field), name, textures)?
+            }
+            _ => Arc::new(ConstantTexture::new(parameters.base_colour)),
+        };
+
+        Ok(Principled::textured(PrincipledTextures {
+            base_colour,
+            metallic: scalar(&self.metallic, "metallic", parameters.metallic)?,
+            roughness: scalar(&self.roughness, "roughness", parameters.roughness)?,
+            specular: scalar(&self.specular, "specular", parameters.specular)?,
+            specular_tint: scalar(
+                &self.specular_tint,
+                "specular_tint",
+                parameters.specular_tint,
+            )?,
+            sheen: scalar(&self.sheen, "sheen", parameters.sheen)?,
+            sheen_tint: scalar(&self.sheen_tint, "sheen_tint", parameters.sheen_tint)?,
+            clearcoat: scalar(&self.clearcoat, "clearcoat", parameters.clearcoat)?,
+            clearcoat_gloss: scalar(
+                &self.clearcoat_gloss,
+                "clearcoat_gloss",
+                parameters.clearcoat_gloss,
+            )?,
+            transmission: scalar(&self.transmission, "transmission", parameters.transmission)?,
+            ior: parameters.ior,
+            anisotropic: scalar(&self.anisotropic, "anisotropic", parameters.anisotropic)?,
+        }))
+    }
+}
+
+impl ShapeDescription {
+    fn build(
+        &self,
+        field: &str,
+        directory: &Path,
+        material_ids: &HashMap<&str, MaterialID>,
+        scene: &mut Scene,
+    ) -> Result<(), SceneError> {
+        match self {
+            ShapeDescription::Sphere {
+                centre,
+                radius,
+                material,
+                transform,
+                reverse_orientation,
+            } => {
+                let material_id = lookup_material(field, material, material_ids)?;
+                check_positive(&format!("{}.radius", field), *radius)?;
+
+                let (object_to_world, _) =
+                    build_transform(&format!("{}.transform", field), transform)?;
+                let object_to_world = transform::translate(Vec3::from(*centre)) * object_to_world;
+                scene.add_object(Box::new(Sphere::from_transform(
+                    object_to_world,
+                    object_to_world.inversed(),
+                    *radius,
+                    material_id,
+                    *reverse_orientation,
+                )));
+            }
+            ShapeDescription::Cylinder {
+                radius,
+                length,
+                material,
+                transform,
+                reverse_orientation,
+            } => {
+                let material_id = lookup_material(field, material, material_ids)?;
+                check_positive(&format!("{}.radius", field), *radius)?;
+                check_positive(&format!("{}.length", field), *length)?;
+
+                let (object_to_world, world_to_object) =
+                    build_transform(&format!("{}.transform", field), transform)?;
+                let half_len = length / 2.0;
+                scene.add_object(Box::new(Cylinder::from_transform(
+                    object_to_world,
+                    world_to_object,
+                    *radius,
+                    -half_len,
+                    half_len,
+                    material_id,
+                    *reverse_orientation,
+                )));
+            }
+            ShapeDescription::Mesh {
+                file,
+                material,
+                transform,
+                reverse_orientation,
+            } => {
+                let material_id = match material {
+                    Some(material) => Some(lookup_material(field, material, material_ids)?),
+                    None => None,
+                };
+                let (object_to_world, world_to_object) =
+                    build_transform(&format!("{}.transform", field), transform)?;
+
+                let path = directory.join(file);
+                let file_field = format!("{}.file", field);
+                match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("obj") => {
+                        let model = ObjModel::load(&path)
+                            .map_err(|err| invalid_error(&file_field, &err.to_string()))?;
+                        model.add_to_scene(
+                            scene,
+                            object_to_world,
+                            world_to_object,
+                            material_id,
+                            *reverse_orientation,
+                        );
+                    }
+                    Some("ply") => {
+                        let mesh = PlyMesh::load(&path)
+                            .map_err(|err| invalid_error(&file_field, &err.to_string()))?;
+                        mesh.add_to_scene(
+                            scene,
+                            object_to_world,
+                            world_to_object,
+                            material_id,
+                            *reverse_orientation,
+                        );
+                    }
+                    _ => {
+                        return invalid(
+                            &file_field,
+                            "unsupported mesh format, expected `obj` or `ply`",
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn build_transform(
+    field: &str,
+    transforms: &[TransformDescription],
+) -> Result<(Mat4, Mat4), SceneError> {
+    let mut object_to_world = Mat4::identity();
+
+    for (i, description) in transforms.iter().enumerate() {
+        let m = match description {
+            TransformDescription::Translate(delta) => transform::translate(Vec3::from(*delta)),
+            TransformDescription::Rotate { angle, axis } => {
+                let axis = Vec3::from(*axis);
+                if axis.mag_sq() == 0.0 {
+                    return invalid(&format!("{}[{}].axis", field, i), "axis can't be zero");
+                }
+                transform::rotate(*angle, axis)
+            }
+            TransformDescription::Scale(factors) => {
+                if factors.contains(&0.0) {
+                    return invalid(&format!("{}[{}]", field, i), "scale can't be zero");
+                }
+                transform::scale(Vec3::from(*factors))
+            }
+        };
+        object_to_world = object_to_world * m;
+    }
+
+    Ok((object_to_world, object_to_world.inversed()))
+}
+
+fn lookup_texture(
+    field: &str,
+    name: &str,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Texture>, SceneError> {
+    match textures.get(name) {
+        Some(texture) => Ok(texture.clone()),
+        None => invalid(field, &format!("unknown texture `{}`", name)),
+    }
+}
+
+fn lookup_material(
+    field: &str,
+    name: &str,
+    material_ids: &HashMap<&str, MaterialID>,
+) -> Result<MaterialID, SceneError> {
+    match material_ids.get(name) {
+        Some(material_id) => Ok(*material_id),
+        None => invalid(
+            &format!("{}.material", field),
+            &format!("unknown material `{}`", name),
+        ),
+    }
+}
+
+fn check_positive(field: &str, value: f32) -> Result<f32, SceneError> {
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        invalid(
+            field,
+            &format!("expected a positive number, found {}", value),
+        )
+    }
+}
+
+fn check_non_negative(field: &str, value: f32) -> Result<f32, SceneError> {
+    if value >= 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        invalid(
+            field,
+            &format!("expected a non-negative number, found {}", value),
+        )
+    }
+}
+
+fn check_unit(field: &str, value: f32) -> Result<f32, SceneError> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        invalid(
+            field,
+            &format!("expected a number from 0 to 1, found {}", value),
+        )
+    }
+}
+
+// Roughness along the two tangents, isotropic unless `roughness_v` is given
+fn build_roughness(
+    field: &str,
+    roughness: &ScalarDescription,
+    roughness_v: &Option<ScalarDescription>,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Roughness, SceneError> {
+    let field_u = format!("{}.roughness", field);
+    let field_v = format!("{}.roughness_v", field);
+    match (roughness, roughness_v) {
+        (ScalarDescription::Constant(roughness), None) => {
+            let roughness = check_unit(&field_u, *roughness)?;
+            Ok(Roughness::new(roughness, roughness))
+        }
+        (
+            ScalarDescription::Constant(roughness_u),
+            Some(ScalarDescription::Constant(roughness_v)),
+        ) => Ok(Roughness::new(
+            check_unit(&field_u, *roughness_u)?,
+            check_unit(&field_v, *roughness_v)?,
+        )),
+        _ => {
+            let roughness_u = roughness.build_unit(&field_u, textures)?;
+            let roughness_v = match roughness_v {
+                Some(roughness_v) => roughness_v.build_unit(&field_v, textures)?,
+                None => roughness_u.clone(),
+            };
+            Ok(Roughness::Textured(roughness_u, roughness_v))
+        }
+    }
+}
+
+fn check_colour(field: &str, colour: &[f64; 3]) -> Result<Colour, SceneError> {
+    if colour.iter().all(|c| *c >= 0.0 && c.is_finite()) {
+        Ok(Colour::new(colour[0], colour[1], colour[2]))
+    } else {
+        invalid(field, "colour components can't be negative")
+    }
+}
+
+fn invalid<T>(field: &str, message: &str) -> Result<T, SceneError> {
+    Err(invalid_error(field, message))
+}
+
+fn invalid_error(field: &str, message: &str) -> SceneError {
+    SceneError::Invalid {
+        field: field.to_string(),
+        message: message.to_string(),
+        location: None,
+    }
+}
+
+impl SourceMap {
+    // The scene has already parsed, so this only fails on values it can't locate, like dates
+    fn new(contents: &str) -> Option<SourceMap> {
+        let children = toml::from_str(contents).ok()?;
+        let line_starts = std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut root = SourceNode {
+            start: None,
+            children,
+        };
+        root.extend_numbers(contents.as_bytes());
+
+        Some(SourceMap { root, line_starts })
+    }
+
+    // Line and column of the value at a field path like `shapes[2].transform[0].axis`, or of the
+    // closest enclosing value that can be found
+    fn locate(&self, field: &str) -> Option<(usize, usize)> {
+        let mut node = &self.root;
+        let mut rest = field;
+        let mut start = None;
+        loop {
+            let next = match &node.children {
+                // Keys can contain dots themselves, so the longest one that matches is used
+                SourceChildren::Table(table) => table
+                    .iter()
+                    .filter(|(key, _)| {
+                        rest.strip_prefix(key.as_str()).is_some_and(|after| {
+                            after.is_empty() || after.starts_with('.') || after.starts_with('[')
+                        })
+                    })
+                    .max_by_key(|(key, _)| key.len())
+                    .map(|(key, child)| (child, &rest[key.len()..])),
+                SourceChildren::Array(array) => rest
+                    .strip_prefix('[')
+                    .and_then(|after| after.split_once(']'))
+                    .and_then(|(index, after)| {
+                        let child = array.get(index.parse::<usize>().ok()?)?;
+                        Some((child, after))
+                    }),
+                SourceChildren::Value => None,
+            };
+            let Some((child, after)) = next else {
+                break;
+            };
+            node = child;
+            rest = after.strip_prefix('.').unwrap_or(after);
+            start = node.start.or_else(|| node.first_start()).or(start);
+        }
+
+        let start = start?;
+        let line = self
+            .line_starts
+            .partition_point(|line_start| *line_start <= start);
+        Some((line, start - self.line_starts[line - 1] + 1))
+    }
+}
+
+impl SourceNode {
+    // The parser only gives the span of the last part of a float, like the `5` of `2.5`, so values
+    // are moved back to the start of the number they're in
+    fn extend_numbers(&mut self, contents: &[u8]) {
+        let in_number = |c: u8| c.is_ascii_digit() || matches!(c, b'.' | b'_' | b'+' | b'-' | b'e');
+        match &mut self.children {
+            SourceChildren::Value => {
+                if let Some(start) = &mut self.start {
+                    while *start > 0 && in_number(contents[*start - 1].to_ascii_lowercase()) {
+                        *start -= 1;
+                    }
+                }
+            }
+            SourceChildren::Table(table) => {
+                for child in table.values_mut() {
+                    child.extend_numbers(contents);
+                }
+            }
+            SourceChildren::Array(array) => {
+                for child in array {
+                    child.extend_numbers(contents);
+                }
+            }
+        }
+    }
+
+    fn first_start(&self) -> Option<usize> {
+        let children: Vec<&SourceNode> = match &self.children {
+            SourceChildren::Value => return None,
+            SourceChildren::Table(table) => table.values().collect(),
+            SourceChildren::Array(array) => array.iter().collect(),
+        };
+
+        children
+            .into_iter()
+            .filter_map(|child| child.start.or_else(|| child.first_start()))
+            .min()
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceNode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let spanned = toml::Spanned::<SourceChildren>::deserialize(deserializer)?;
+        let start = match spanned.span() {
+            (0, 0) => None,
+            (start, _) => Some(start),
+        };
+
+        Ok(SourceNode {
+            start,
+            children: spanned.into_inner(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceChildren {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SourceChildrenVisitor)
+    }
+}
+
+struct SourceChildrenVisitor;
+
+impl<'de> serde::de::Visitor<'de> for SourceChildrenVisitor {
+    type Value = SourceChildren;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a TOML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<SourceChildren, E> {
+        Ok(SourceChildren::Value)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<SourceChildren, E> {
+        Ok(SourceChildren::Value)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<SourceChildren, E> {
+        Ok(SourceChildren::Value)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<SourceChildren, E> {
+        Ok(SourceChildren::Value)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<SourceChildren, E> {
+        Ok(SourceChildren::Value)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<SourceChildren, A::Error> {
+        let mut array = Vec::new();
+        while let Some(child) = seq.next_element()? {
+            array.push(child);
+        }
+
+        Ok(SourceChildren::Array(array))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> Result<SourceChildren, A::Error> {
+        let mut table = BTreeMap::new();
+        while let Some((key, child)) = map.next_entry()? {
+            table.insert(key, child);
+        }
+
+        Ok(SourceChildren::Table(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "[camera]\norigin = [0.0, 0.0, -5.0]\ntarget = [0.0, 0.0, 0.0]\n";
+
+    // Error from building a TOML scene that parses but is expected not to build
+    fn build_error(source: &str) -> SceneError {
+        let description = SceneDescription::from_toml(source).expect("scene should parse");
+        match description.build(1.0) {
+            Ok(_) => panic!("scene should fail to build"),
+            Err(err) => err,
+        }
+    }
+
+    fn assert_invalid(err: SceneError, expected_field: &str, expected_location: (usize, usize)) {
+        match err {
+            SceneError::Invalid {
+                field, location, ..
+            } => {
+                assert_eq!(field, expected_field);
+                assert_eq!(location, Some(expected_location));
+            }
+            err => panic!("expected an invalid field, found `{}`", err),
+        }
+    }
+
+    fn assert_message(err: SceneError, expected: &str) {
+        match err {
+            SceneError::Invalid { message, .. } => assert!(
+                message.contains(expected),
+                "expected `{}` in `{}`",
+                expected,
+                message
+            ),
+            err => panic!("expected an invalid field, found `{}`", err),
+        }
+    }
+
+    #[test]
+    fn locates_values_in_tables() {
+        let source = format!(
+            "{}
+[materials.foo]
+type = \"diffuse\"
+albedo = [0.5, -1.0, 0.5]
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"foo\"
+",
+            CAMERA
+        );
+
+        assert_invalid(build_error(&source), "materials.foo.albedo", (7, 10));
+    }
+
+    #[test]
+    fn locates_floats() {
+        let source = format!(
+            "{}
+[materials.foo]
+type = \"diffuse\"
+albedo = [0.5, 0.5, 0.5]
+sigma = 100.0
+
+[[shapes]]
+type = \"sphere\"
+radius = -1.5e2
+material = \"foo\"
+",
+            CAMERA
+        );
+        assert_invalid(build_error(&source), "materials.foo.sigma", (8, 9));
+
+        let source = source.replace("100.0", "10.0");
+        assert_invalid(build_error(&source), "shapes[0].radius", (12, 10));
+    }
+
+    #[test]
+    fn locates_values_in_arrays() {
+        let source = format!(
+            "{}
+[materials.foo]
+type = \"diffuse\"
+albedo = [0.5, 0.5, 0.5]
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"foo\"
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"foo\"
+transform = [
+    {{ translate = [1.0, 0.0, 0.0] }},
+    {{ scale = [1.0, 0.0, 1.0] }},
+]
+",
+            CAMERA
+        );
+
+        assert_invalid(build_error(&source), "shapes[1].transform[1]", (20, 5));
+    }
+
+    #[test]
+    fn unknown_materials_are_errors() {
+        let shape = format!(
+            "{}
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"missing\"
+",
+            CAMERA
+        );
+        assert_message(build_error(&shape), "unknown material `missing`");
+
+        let mix = format!(
+            "{}
+[materials.mix]
+type = \"mix\"
+materials = [\"missing\", \"missing\"]
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"mix\"
+",
+            CAMERA
+        );
+        assert_message(build_error(&mix), "unknown material `missing`");
+    }
+
+    #[test]
+    fn material_cycles_are_errors() {
+        let source = format!(
+            "{}
+[materials.a]
+type = \"mix\"
+materials = [\"b\", \"b\"]
+
+[materials.b]
+type = \"twosided\"
+front = \"a\"
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"a\"
+",
+            CAMERA
+        );
+
+        assert_message(build_error(&source), "references itself");
+    }
+
+    #[test]
+    fn mixes_of_bumped_or_subsurface_materials_are_errors() {
+        let mix = |material: &str| {
+            format!(
+                "{}
+[textures.checks]
+type = \"checker\"
+colours = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]
+
+[materials.plain]
+type = \"diffuse\"
+albedo = [0.5, 0.5, 0.5]
+
+[materials.bumped]
+type = \"bump\"
+material = \"plain\"
+height = \"checks\"
+
+[materials.skin]
+type = \"subsurface\"
+albedo = [0.9, 0.8, 0.7]
+mean_free_path = [1.0, 0.5, 0.2]
+
+[materials.sides]
+type = \"twosided\"
+front = \"skin\"
+back = \"plain\"
+
+[materials.a]
+type = \"mix\"
+materials = [\"plain\", \"{}\"]
+amount = 0.5
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"a\"
+",
+                CAMERA, material
+            )
+        };
+
+        assert_message(build_error(&mix("bumped")), "bump or normal mapped");
+        assert_message(build_error(&mix("skin")), "inside the shape");
+        assert_message(build_error(&mix("sides")), "`sides` can't be mixed");
+    }
+
+    #[test]
+    fn subsurface_backs_are_errors() {
+        let source = format!(
+            "{}
+[materials.plain]
+type = \"diffuse\"
+albedo = [0.5, 0.5, 0.5]
+
+[materials.skin]
+type = \"subsurface\"
+albedo = [0.9, 0.8, 0.7]
+mean_free_path = [1.0, 0.5, 0.2]
+
+[materials.sides]
+type = \"twosided\"
+front = \"plain\"
+back = \"skin\"
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"sides\"
+",
+            CAMERA
+        );
+
+        assert_invalid(build_error(&source), "materials.sides.back", (17, 8));
+    }
+
+    #[test]
+    fn material_parameters_take_textures() {
+        let scene = |principled: &str| {
+            format!(
+                "{}
+[textures.checks]
+type = \"checker\"
+colours = [[0.1, 0.1, 0.1], [0.9, 0.9, 0.9]]
+
+[materials.metal]
+type = \"metal\"
+albedo = \"checks\"
+roughness = \"checks\"
+roughness_v = 0.5
+
+[materials.plastic]
+type = \"plastic\"
+albedo = \"checks\"
+roughness = \"checks\"
+
+[materials.skin]
+type = \"subsurface\"
+albedo = \"checks\"
+mean_free_path = [1.0, 0.5, 0.2]
+
+[materials.glass]
+type = \"dielectric\"
+ior = 1.5
+roughness = \"checks\"
+
+[materials.principled]
+type = \"principled\"
+{}
+
+[[shapes]]
+type = \"sphere\"
+radius = 1.0
+material = \"metal\"
+",
+                CAMERA, principled
+            )
+        };
+
+        let source = scene("base_colour = \"checks\"\nroughness = \"checks\"\nmetallic = 0.5");
+        let description = SceneDescription::from_toml(&source).expect("scene should parse");
+        assert!(description.build(1.0).is_ok());
+
+        let err = build_error(&scene("roughness = \"dents\""));
+        assert_invalid(err, "materials.principled.roughness", (32, 13));
+        let err = build_error(&scene("roughness = \"checks\"\nmetallic = 2.0"));
+        assert_invalid(err, "materials.principled.metallic", (33, 12));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::pbrt::PbrtScene;
+use crate::scene_file::{RenderDescription, SceneDescription};
+use clap::{App, Arg, ArgMatches};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Integrator {
+    PathTracer,
+    // Path tracing without light sampling, to check the path tracer converges to the same image
+    Reference,
+    Normals,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Integrator::PathTracer),
+            "reference" => Ok(Integrator::Reference),
+            "normals" => Ok(Integrator::Normals),
+            _ => Err(format!("Unknown integrator `{}`", s)),
+        }
+    }
+}
+
+pub enum SceneSource {
+    Builtin(String),
+    File(PathBuf, Box<SceneDescription>),
+    Pbrt(Box<PbrtScene>),
+}
+
+impl SceneSource {
+    const BUILTIN: [&'static str; 2] = ["default", "furnace"];
+
+    fn from_arg(scene: &str) -> Result<SceneSource, String> {
+        if Self::BUILTIN.contains(&scene) {
+            return Ok(SceneSource::Builtin(scene.to_string()));
+        }
+
+        let path = PathBuf::from(scene);
+        if path.extension().and_then(|extension| extension.to_str()) == Some("pbrt") {
+            return match PbrtScene::load(&path) {
+                Ok(pbrt) => {
+                    for warning in pbrt.warnings.iter() {
+                        eprintln!("warning: {}", warning);
+                    }
+                    Ok(SceneSource::Pbrt(Box::new(pbrt)))
+                }
+                Err(err) => Err(format!("Couldn't load scene `{}`: {}", scene, err)),
+            };
+        }
+
+        match SceneDescription::from_file(&path) {
+            Ok(description) => Ok(SceneSource::File(path, Box::new(description))),
+            Err(err) => Err(format!("Couldn't load scene `{}`: {}", scene, err)),
+        }
+    }
+
+    fn render_description(&self) -> Option<&RenderDescription> {
+        match self {
+            SceneSource::Builtin(_) => None,
+            SceneSource::File(_, description) => Some(&description.render),
+            SceneSource::Pbrt(pbrt) => Some(&pbrt.render),
+        }
+    }
+}
+
+pub struct Settings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub threads: Option<usize>,
+    pub output: PathBuf,
+    pub integrator: Integrator,
+    pub seed: Option<u64>,
+}
+
+impl Settings {
+    // The scene is returned separately so it can be consumed while the settings are borrowed
+    pub fn from_args() -> (Settings, SceneSource) {
+        let matches = Self::app().get_matches();
+
+        match Self::from_matches(&matches) {
+            Ok(result) => result,
+            Err(message) => {
+                clap::Error::with_description(&message, clap::ErrorKind::ValueValidation).exit()
+            }
+        }
+    }
+
+    fn app() -> App<'static, 'static> {
+        App::new(env!("CARGO_PKG_NAME"))
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("Renders a scene with a CPU path tracer")
+            .arg(
+                Arg::with_name("width")
+                    .long("width")
+                    .value_name("PIXELS")
+                    .help("Width of the output image [default: 480]")
+                    .validator(validate_positive),
+            )
+            .arg(
+                Arg::with_name("height")
+                    .long("height")
+                    .value_name("PIXELS")
+                    .help("Height of the output image [default: width / aspect ratio]")
+                    .validator(validate_positive),
+            )
+            .arg(
+                Arg::with_name("aspect-ratio")
+                    .long("aspect-ratio")
+                    .value_name("RATIO")
+                    .help("Aspect ratio used to derive the height, e.g. `16:9` or `1.78` [default: 4:3]")
+                    .conflicts_with("height")
+                    .validator(|s| parse_aspect_ratio(&s).map(|_| ())),
+            )
+            .arg(
+                Arg::with_name("samples")
+                    .long("samples")
+                    .short("s")
+                    .value_name("COUNT")
+                    .help("Samples per pixel [default: 1000]")
+                    .validator(validate_positive),
+            )
+            .arg(
+                Arg::with_name("max-depth")
+                    .long("max-depth")
+                    .short("d")
+                    .value_name("BOUNCES")
+                    .help("Maximum number of bounces per path [default: 10]")
+                    .validator(validate_positive),
+            )
+            .arg(
+                Arg::with_name("tile-size")
+                    .long("tile-size")
+                    .value_name("SIZE")
+                    .help("Size of a render tile, either `N` or `WxH`")
+                    .default_value("16x16")
+                    .validator(|s| parse_tile_size(&s).map(|_| ())),
+            )
+            .arg(
+                Arg::with_name("threads")
+                    .long("threads")
+                    .short("t")
+                    .value_name("COUNT")
+                    .help("Number of render threads [default: number of logical cores]")
+                    .validator(validate_positive),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .value_name("FILE")
+                    .help("Path of the rendered image")
+                    .default_value("output.png"),
+            )
+            .arg(
+                Arg::with_name("integrator")
+                    .long("integrator")
+                    .short("i")
+                    .value_name("MODE")
+                    .help("Integrator used to shade camera rays")
+                    .possible_values(&["path", "reference", "normals"])
+                    .default_value("path"),
+            )
+            .arg(
+                Arg::with_name("scene")
+                    .long("scene")
+                    .value_name("SCENE")
+                    .help(
+                        "Scene file (`.toml`, `.json` or `.pbrt`) or built-in scene (`default`, `furnace`)",
+                    )
+                    .default_value("default"),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .value_name("SEED")
+                    .help("Seed for reproducible renders [default: random]")
+                    .validator(|s| parse_value::<u64>(&s, "seed").map(|_| ())),
+            )
+    }
+
+    // Explicit command-line values take precedence over the scene file's render settings
+    fn from_matches(matches: &ArgMatches) -> Result<(Settings, SceneSource), String> {
+        let scene = SceneSource::from_arg(matches.value_of("scene").unwrap())?;
+        let render = scene.render_description();
+        let render_value = |value: fn(&RenderDescription) -> Option<u32>| render.and_then(value);
+
+        let image_width = match matches.value_of("width") {
+            Some(width) => parse_value(width, "width")?,
+            None => render_value(|r| r.width).unwrap_or(480),
+        };
+        let image_height = match matches.value_of("height") {
+            Some(height) => parse_value(height, "height")?,
+            None => {
+                let aspect_ratio = match matches.value_of("aspect-ratio") {
+                    Some(aspect_ratio) => parse_aspect_ratio(aspect_ratio)?,
+                    None => match (render_value(|r| r.width), render_value(|r| r.height)) {
+                        (Some(width), Some(height)) => width as f32 / height as f32,
+                        _ => 4.0 / 3.0,
+                    },
+                };
+                ((image_width as f32 / aspect_ratio) as u32).max(1)
+            }
+        };
+        let samples = match matches.value_of("samples") {
+            Some(samples) => parse_value(samples, "samples")?,
+            None => render_value(|r| r.samples).unwrap_or(1000),
+        };
+        let max_depth = match matches.value_of("max-depth") {
+            Some(max_depth) => parse_value(max_depth, "max-depth")?,
+            None => render_value(|r| r.max_depth).unwrap_or(10),
+        };
+        if image_width == 0 || image_height == 0 || samples == 0 || max_depth == 0 {
+            return Err("Render settings must be positive".to_string());
+        }
+
+        let (tile_width, tile_height) = parse_tile_size(matches.value_of("tile-size").unwrap())?;
+        let threads = match matches.value_of("threads") {
+            Some(threads) => Some(parse_value(threads, "threads")?),
+            None => None,
+        };
+        let seed = match matches.value_of("seed") {
+            Some(seed) => Some(parse_value(seed, "seed")?),
+            None => None,
+        };
+
+        let settings = Settings {
+            image_width,
+            image_height,
+            samples,
+            max_depth,
+            tile_width,
+            tile_height,
+            threads,
+            output: PathBuf::from(matches.value_of("output").unwrap()),
+            integrator: matches.value_of("integrator").unwrap().parse()?,
+            seed,
+        };
+
+        Ok((settings, scene))
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.image_width as f32 / self.image_height as f32
+    }
+
+    pub fn tiles_x(&self) -> u32 {
+        self.image_width.div_ceil(self.tile_width)
+    }
+
+    pub fn tiles_y(&self) -> u32 {
+        self.image_height.div_ceil(self.tile_height)
+    }
+
+    pub fn total_tiles(&self) -> u32 {
+        self.tiles_x() * self.tiles_y()
+    }
+
+    // Seeding per tile keeps renders reproducible regardless of thread scheduling
+    pub fn tile_rng(&self, tile_x: u32, tile_y: u32) -> StdRng {
+        match self.seed {
+            Some(seed) => {
+                let tile_index = (tile_y * self.tiles_x() + tile_x) as u64;
+                StdRng::seed_from_u64(seed ^ tile_index.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            }
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(s: &str, name: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("Invalid value `{}` for {}", s, name))
+}
+
+fn validate_positive(s: String) -> Result<(), String> {
+    match s.parse::<u32>() {
+        Ok(value) if value > 0 => Ok(()),
+        _ => Err(format!("`{}` isn't a positive integer", s)),
+    }
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+    let ratio = match s.find(':') {
+        Some(index) => {
+            let width: f32 = parse_value(&s[..index], "aspect ratio")?;
+            let height: f32 = parse_value(&s[index + 1..], "aspect ratio")?;
+            width / height
+        }
+        None => parse_value(s, "aspect ratio")?,
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("`{}` isn't a valid aspect ratio", s))
+    }
+}
+
+fn parse_tile_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = match s.find('x') {
+        Some(index) => (&s[..index], &s[index + 1..]),
+        None => (s, s),
+    };
+    let width: u32 = parse_value(width, "tile size")?;
+    let height: u32 = parse_value(height, "tile size")?;
+
+    if width > 0 && height > 0 {
+        Ok((width, height))
+    } else {
+        Err(format!("`{}` isn't a valid tile size", s))
+    }
+}
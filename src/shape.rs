@@ -1,5 +1,9 @@
 use crate::bounds::Bounds3;
 use crate::intersectable::{IntersectRecord, Intersectable};
+use crate::material::MaterialID;
+use crate::ray::Ray;
+use crate::transform::transform_normal;
+use crate::utils::linear_determinant;
 use ultraviolet::{Mat4, Vec2, Vec3};
 
 #[allow(dead_code)]
@@ -18,9 +22,19 @@ pub trait Shape: Intersectable {
 
     fn transform_swaps_handedness(&self) -> bool;
 
+    fn material_id(&self) -> MaterialID;
+
+    // Identifies the surface the shape is part of, which is the shape itself unless it's one
+    // piece of a larger surface like a triangle of a mesh
+    fn surface(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
     fn area(&self) -> f32;
 
-    fn pdf_wi(&self, rec: &IntersectRecord, wi: &Vec3) -> f32;
+    fn pdf_wi(&self, rec: &IntersectRecord, wi: &Vec3) -> f32 {
+        area_pdf_wi(self, rec, wi)
+    }
 
     fn pdf(&self, _rec: &IntersectRecord) -> f32 {
         1.0 / self.area()
@@ -32,3 +46,28 @@ pub trait Shape: Intersectable {
         self.sample(u)
     }
 }
+
+// Converts the area density of `sample` to solid angle at the reference point
+pub fn area_pdf_wi<S: Shape + ?Sized>(shape: &S, rec: &IntersectRecord, wi: &Vec3) -> f32 {
+    let ray = Ray::new(rec.point, *wi, 0.0, f32::INFINITY);
+    match shape.intersect(&ray, false) {
+        Some((light_rec, t_hit)) => {
+            let distance_sq = (ray.at(t_hit) - rec.point).mag_sq();
+            let cos_theta = light_rec.normal.dot(-*wi).abs();
+            if cos_theta == 0.0 {
+                0.0
+            } else {
+                distance_sq * shape.pdf(&light_rec) / cos_theta
+            }
+        }
+        None => 0.0,
+    }
+}
+
+// How much the shape's transform scales areas of its surface around the object space `normal`,
+// for converting densities over the object space surface to world space
+pub fn area_scale<S: Shape + ?Sized>(shape: &S, normal: &Vec3) -> f32 {
+    let determinant = linear_determinant(shape.object_to_world()).abs();
+
+    determinant * transform_normal(shape.world_to_object(), *normal).mag()
+}
@@ -1,14 +1,19 @@
 use crate::bounds::Bounds3;
-use crate::intersectable::{IntersectRecord, Intersectable};
+use crate::intersectable::{IntersectRecord, Intersectable, SurfaceDifferentials};
 use crate::material::MaterialID;
 use crate::ray::Ray;
-use crate::shape::Shape;
-use crate::utils::{quadratic, transform_swaps_handedness};
+use crate::shape::{area_pdf_wi, area_scale, Shape};
+use crate::transform::{transform_normal, transform_point, transform_vector};
+use crate::utils::{
+    create_coordinates_system, quadratic, transform_swaps_handedness, uniform_sample_sphere,
+    uniform_scale,
+};
 use std::f32::consts::PI;
 use ultraviolet::{Mat4, Vec2, Vec3};
 
 pub struct Sphere {
     pub centre: Vec3,
+    // Radius in object space
     pub radius: f32,
     pub material_id: MaterialID,
 
@@ -16,6 +21,10 @@ pub struct Sphere {
     world_to_object: Mat4,
     reverse_orientation: bool,
     transform_swaps_handedness: bool,
+    // Radius in world space if the transform keeps the sphere round, so lights can be sampled
+    // within the cone they cover
+    world_radius: Option<f32>,
+    area: f32,
 }
 
 impl Sphere {
@@ -27,17 +36,14 @@ impl Sphere {
     ) -> Sphere {
         let object_to_world = Mat4::from_translation(centre);
         let world_to_object = Mat4::from_translation(-centre);
-        let transform_swaps_handedness = transform_swaps_handedness(&object_to_world);
 
-        Sphere {
-            centre,
-            radius,
-            material_id,
+        Sphere::from_transform(
             object_to_world,
             world_to_object,
+            radius,
+            material_id,
             reverse_orientation,
-            transform_swaps_handedness,
-        }
+        )
     }
 
     pub fn from_transform(
@@ -49,8 +55,9 @@ impl Sphere {
     ) -> Sphere {
         let transform_swaps_handedness = transform_swaps_handedness(&object_to_world);
         let centre = object_to_world.cols[3].xyz();
+        let world_radius = uniform_scale(&object_to_world).map(|scale| radius * scale);
 
-        Sphere {
+        let mut sphere = Sphere {
             centre,
             radius,
             material_id,
@@ -58,42 +65,123 @@ impl Sphere {
             world_to_object,
             reverse_orientation,
             transform_swaps_handedness,
+            world_radius,
+            area: 0.0,
+        };
+        sphere.area = sphere.world_area();
+
+        sphere
+    }
+
+    // Squashed spheres have no closed form for their area, so it's integrated over the object
+    // space sphere, whose area is spread evenly over its height
+    fn world_area(&self) -> f32 {
+        if let Some(world_radius) = self.world_radius {
+            return 4.0 * PI * world_radius.powi(2);
         }
+
+        const STEPS: usize = 64;
+        let mut sum = 0.0;
+        for i in 0..STEPS {
+            let z = (i as f32 + 0.5) / STEPS as f32 * 2.0 - 1.0;
+            let r = (1.0 - z.powi(2)).sqrt();
+            for j in 0..2 * STEPS {
+                let phi = (j as f32 + 0.5) / (2 * STEPS) as f32 * 2.0 * PI;
+                sum += area_scale(self, &Vec3::new(r * phi.cos(), r * phi.sin(), z));
+            }
+        }
+
+        4.0 * PI * self.radius.powi(2) * sum / (2 * STEPS * STEPS) as f32
+    }
+
+    // World space normal from the outward normal in object space. Transforming normals keeps them
+    // outward even when the transform swaps handedness, so only the orientation flips them
+    #[inline]
+    fn orient(&self, local_normal: Vec3) -> Vec3 {
+        let normal = transform_normal(&self.world_to_object, local_normal).normalized();
+        if self.reverse_orientation {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    // Record for a point on the surface, given in both world and object space. `u` goes around
+    // the z axis from +x and `v` from the bottom pole to the top, as in pbrt
+    fn record(&self, point: Vec3, local: Vec3, normal: Vec3, front_face: bool) -> IntersectRecord {
+        let mut phi = local.y.atan2(local.x);
+        if phi < 0.0 {
+            phi += 2.0 * PI;
+        }
+        let theta = (local.z / self.radius).clamp(-1.0, 1.0).acos();
+        let z_radius = (local.x.powi(2) + local.y.powi(2)).sqrt();
+
+        let dpdu = Vec3::new(-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0);
+        let dpdv = Vec3::new(local.z * phi.cos(), local.z * phi.sin(), -z_radius) * -PI;
+
+        IntersectRecord {
+            point,
+            object_point: local,
+            normal,
+            shading_normal: normal,
+            uv: Vec2::new(phi / (2.0 * PI), 1.0 - theta / PI),
+            dpdu: transform_vector(&self.object_to_world, dpdu),
+            dpdv: transform_vector(&self.object_to_world, dpdv),
+            object_dpdu: dpdu,
+            object_dpdv: dpdv,
+            differentials: SurfaceDifferentials::default(),
+            vertex_colour: None,
+            front_face,
+            material_id: self.material_id,
+        }
+    }
+
+    // Record for a point on the surface in object space, facing the front
+    fn sampled_record(&self, local: Vec3) -> IntersectRecord {
+        let point = transform_point(&self.object_to_world, local);
+
+        self.record(point, local, self.orient(local), true)
+    }
+
+    // Cosine of the half-angle of the cone containing the sphere as seen from `point`, or `None`
+    // if the point is inside the sphere
+    fn cos_theta_max(&self, world_radius: f32, point: &Vec3) -> Option<f32> {
+        let distance_sq = (self.centre - *point).mag_sq();
+        let radius_sq = world_radius.powi(2);
+        if distance_sq <= radius_sq {
+            return None;
+        }
+
+        Some((1.0 - radius_sq / distance_sq).max(0.0).sqrt())
     }
 }
 
 impl Intersectable for Sphere {
     fn intersect(&self, ray: &Ray, _test_alpha_textures: bool) -> Option<(IntersectRecord, f32)> {
-        let oc = ray.origin - self.centre;
-        let a = ray.direction.mag_sq();
-        let b = 2.0 * oc.dot(ray.direction);
-        let c = oc.mag_sq() - self.radius.powi(2);
-        if let Some((t0, t1)) = quadratic(a as f64, b as f64, c as f64) {
-            return if t0 > ray.t_max || t1 <= ray.t_min {
-                None
-            } else {
-                let mut t_hit = t0;
-                if t_hit <= ray.t_min {
-                    t_hit = t1;
-                    if t1 > ray.t_max {
-                        return None;
-                    }
-                }
-
-                let point = ray.at(t_hit);
-                let normal = (point - self.centre).normalized();
-
-                Some((
-                    IntersectRecord {
-                        point,
-                        normal,
-                        material_id: self.material_id,
-                    },
-                    t_hit,
-                ))
-            };
+        let r = self.world_to_object * ray;
+        let a = r.direction.mag_sq();
+        let b = 2.0 * r.origin.dot(r.direction);
+        let c = r.origin.mag_sq() - self.radius.powi(2);
+        let (t0, t1) = quadratic(a as f64, b as f64, c as f64)?;
+        if t0 > ray.t_max || t1 <= ray.t_min {
+            return None;
         }
-        None
+        let mut t_hit = t0;
+        if t_hit <= ray.t_min {
+            t_hit = t1;
+            if t1 > ray.t_max {
+                return None;
+            }
+        }
+
+        // Pushed back onto the surface to undo the error in solving for the hit
+        let mut local = r.at(t_hit);
+        local *= self.radius / local.mag();
+        let point = transform_point(&self.object_to_world, local);
+        let normal = self.orient(local);
+        let front_face = ray.direction.dot(normal) < 0.0;
+
+        Some((self.record(point, local, normal, front_face), t_hit))
     }
 }
 
@@ -125,24 +213,81 @@ impl Shape for Sphere {
         self.transform_swaps_handedness
     }
 
+    #[inline]
+    fn material_id(&self) -> MaterialID {
+        self.material_id
+    }
+
     #[inline]
     fn area(&self) -> f32 {
-        4.0 * PI * self.radius.powi(2)
+        self.area
     }
 
-    fn pdf_wi(&self, _rec: &IntersectRecord, _wi: &Vec3) -> f32 {
-        unimplemented!()
+    // Points are sampled evenly over the object space sphere, so the world space density depends
+    // on how much the transform stretches the surface there
+    fn pdf(&self, rec: &IntersectRecord) -> f32 {
+        let normal = rec.object_point.normalized();
+
+        1.0 / (4.0 * PI * self.radius.powi(2) * area_scale(self, &normal))
     }
 
-    fn pdf(&self, _rec: &IntersectRecord) -> f32 {
-        unimplemented!()
+    // Samples directions in the cone of the visible cap, falling back to the whole surface
+    // when the reference point is inside the sphere or the sphere has been squashed
+    fn pdf_wi(&self, rec: &IntersectRecord, wi: &Vec3) -> f32 {
+        let cone = self
+            .world_radius
+            .and_then(|world_radius| self.cos_theta_max(world_radius, &rec.point));
+        match cone {
+            Some(cos_theta_max) => 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+            None => area_pdf_wi(self, rec, wi),
+        }
     }
 
-    fn sample(&self, _point: &Vec2) -> IntersectRecord {
-        unimplemented!()
+    fn sample(&self, point: &Vec2) -> IntersectRecord {
+        self.sampled_record(uniform_sample_sphere(point) * self.radius)
     }
 
-    fn sample_record(&self, _rec: &IntersectRecord, _u: &Vec2) -> IntersectRecord {
-        unimplemented!()
+    fn sample_record(&self, rec: &IntersectRecord, u: &Vec2) -> IntersectRecord {
+        let world_radius = match self.world_radius {
+            Some(world_radius) => world_radius,
+            None => return self.sample(u),
+        };
+        let cos_theta_max = match self.cos_theta_max(world_radius, &rec.point) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return self.sample(u),
+        };
+
+        let distance = (self.centre - rec.point).mag();
+        let sin_theta_max_sq = (world_radius / distance).powi(2);
+
+        // Small cones need the Taylor expansion to keep precision
+        let (sin_theta_sq, cos_theta) = if sin_theta_max_sq < 0.00068523 {
+            let sin_theta_sq = sin_theta_max_sq * u.x;
+            (sin_theta_sq, (1.0 - sin_theta_sq).sqrt())
+        } else {
+            let cos_theta = (1.0 - u.x) + u.x * cos_theta_max;
+            (1.0 - cos_theta.powi(2), cos_theta)
+        };
+        let sin_theta_sq = sin_theta_sq.max(0.0);
+
+        // Angle from the centre to the sampled point on the sphere
+        let ds = distance * cos_theta
+            - (world_radius.powi(2) - distance.powi(2) * sin_theta_sq)
+                .max(0.0)
+                .sqrt();
+        let cos_alpha = ((distance.powi(2) + world_radius.powi(2) - ds.powi(2))
+            / (2.0 * distance * world_radius))
+            .clamp(-1.0, 1.0);
+        let sin_alpha = (1.0 - cos_alpha.powi(2)).max(0.0).sqrt();
+        let phi = u.y * 2.0 * PI;
+
+        let wc = (self.centre - rec.point) / distance;
+        let (wc_x, wc_y) = create_coordinates_system(&wc);
+        let normal =
+            -(wc_x * sin_alpha * phi.cos() + wc_y * sin_alpha * phi.sin() + wc * cos_alpha);
+        let point = self.centre + normal * world_radius;
+        let local = transform_point(&self.world_to_object, point);
+
+        self.record(point, local, self.orient(local), true)
     }
 }
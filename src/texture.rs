@@ -0,0 +1,543 @@
+use crate::colour::Colour;
+use crate::intersectable::IntersectRecord;
+use crate::noise::{worley, Perlin};
+use crate::transform::transform_point;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use ultraviolet::{Mat4, Vec2, Vec3};
+
+#[derive(Debug)]
+pub struct TextureError(PathBuf, image::ImageError);
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.0.display(), self.1)
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+// Colour varying over a surface, looked up by the UVs and position of a hit
+pub trait Texture: Send + Sync {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour;
+
+    // Average of the channels, for parameters that are a single number
+    fn evaluate_scalar(&self, rec: &IntersectRecord) -> f64 {
+        let colour = self.evaluate(rec);
+        (colour.r + colour.g + colour.b) / 3.0
+    }
+}
+
+pub struct ConstantTexture {
+    pub value: Colour,
+}
+
+impl ConstantTexture {
+    pub fn new(value: Colour) -> ConstantTexture {
+        ConstantTexture { value }
+    }
+
+    // Grey, for parameters that are a single number
+    pub fn scalar(value: f32) -> ConstantTexture {
+        let value = value as f64;
+        ConstantTexture::new(Colour::new(value, value, value))
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn evaluate(&self, _rec: &IntersectRecord) -> Colour {
+        self.value
+    }
+}
+
+// How lookups outside the image are treated
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Black,
+}
+
+// Colour interpolated from the vertices of meshes with vertex colours, or `fallback` elsewhere
+pub struct VertexColour {
+    fallback: Colour,
+}
+
+impl VertexColour {
+    pub fn new(fallback: Colour) -> VertexColour {
+        VertexColour { fallback }
+    }
+}
+
+impl Texture for VertexColour {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        rec.vertex_colour.unwrap_or(self.fallback)
+    }
+}
+
+// How an image texture is filtered over the footprint of a lookup
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    // Only interpolates the full resolution image, so distant textures alias
+    Bilinear,
+    // Blends the two MIP levels nearest the footprint's widest extent, blurring at grazing angles
+    #[default]
+    Trilinear,
+    // Elliptically weighted average over the footprint, sharper at grazing angles but slower
+    Ewa,
+}
+
+// Footprints can stretch into very thin ellipses, which are widened so the lookup stays bounded
+const MAX_ANISOTROPY: f32 = 8.0;
+
+// One level of an image's MIP map
+struct MipLevel {
+    width: usize,
+    height: usize,
+    // Linear colours, row by row from the top
+    texels: Vec<Colour>,
+}
+
+impl MipLevel {
+    // Texel at column `x` and row `y` counting up from the bottom, wrapped into the image
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Colour {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (x, y) = match wrap {
+            WrapMode::Repeat => (x.rem_euclid(width), y.rem_euclid(height)),
+            WrapMode::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+            WrapMode::Black => {
+                if x < 0 || x >= width || y < 0 || y >= height {
+                    return Colour::default();
+                }
+                (x, y)
+            }
+        };
+
+        self.texels[(height - 1 - y) as usize * self.width + x as usize]
+    }
+
+    // Bilinear interpolation between the four texel centres around `uv`
+    fn bilerp(&self, uv: &Vec2, wrap: WrapMode) -> Colour {
+        let s = uv.x as f64 * self.width as f64 - 0.5;
+        let t = uv.y as f64 * self.height as f64 - 0.5;
+        let (x, y) = (s.floor(), t.floor());
+        let (ds, dt) = (s - x, t - y);
+        let (x, y) = (x as i64, y as i64);
+
+        self.texel(x, y, wrap) * ((1.0 - ds) * (1.0 - dt))
+            + self.texel(x + 1, y, wrap) * (ds * (1.0 - dt))
+            + self.texel(x, y + 1, wrap) * ((1.0 - ds) * dt)
+            + self.texel(x + 1, y + 1, wrap) * (ds * dt)
+    }
+
+    // Half the resolution, rounding up, averaging each block of four texels. Odd rows and columns
+    // at the edge are averaged with themselves
+    fn downsample(&self) -> MipLevel {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in (0..height as i64).rev() {
+            for x in 0..width as i64 {
+                let mut sum = Colour::default();
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    sum += self.texel(2 * x + dx, 2 * y + dy, WrapMode::Clamp);
+                }
+                texels.push(sum / 4.0);
+            }
+        }
+
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    // Gaussian weighted average of the texels inside the ellipse with axes `dst0` and `dst1`
+    // around `uv`, following pbrt
+    fn ewa(&self, uv: &Vec2, dst0: &Vec2, dst1: &Vec2, wrap: WrapMode) -> Colour {
+        let scale = |v: &Vec2| (v.x * self.width as f32, v.y * self.height as f32);
+        let s = uv.x * self.width as f32 - 0.5;
+        let t = uv.y * self.height as f32 - 0.5;
+        let (ds0, dt0) = scale(dst0);
+        let (ds1, dt1) = scale(dst1);
+
+        // Implicit ellipse A s^2 + B s t + C t^2 = 1, padded by a texel so it covers at least one
+        let mut a = dt0.powi(2) + dt1.powi(2) + 1.0;
+        let mut b = -2.0 * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0.powi(2) + ds1.powi(2) + 1.0;
+        let inv_f = 1.0 / (a * c - b.powi(2) * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // Bounding box of the ellipse
+        let determinant = -b.powi(2) + 4.0 * a * c;
+        let inv_determinant = 1.0 / determinant;
+        let s_radius = 2.0 * inv_determinant * (determinant * c).sqrt();
+        let t_radius = 2.0 * inv_determinant * (determinant * a).sqrt();
+        let (s0, s1) = ((s - s_radius).ceil() as i64, (s + s_radius).floor() as i64);
+        let (t0, t1) = ((t - t_radius).ceil() as i64, (t + t_radius).floor() as i64);
+
+        let mut sum = Colour::default();
+        let mut weight_sum = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f32 - t;
+            for is in s0..=s1 {
+                let ss = is as f32 - s;
+                let r2 = a * ss.powi(2) + b * ss * tt + c * tt.powi(2);
+                if r2 < 1.0 {
+                    let weight = ((-2.0 * r2).exp() - (-2.0f32).exp()) as f64;
+                    sum += self.texel(is, it, wrap) * weight;
+                    weight_sum += weight;
+                }
+            }
+        }
+
+        if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            self.bilerp(uv, wrap)
+        }
+    }
+}
+
+// Image mapped over the unit square of UVs, with (0, 0) at the bottom left of the image. Lookups
+// are filtered over the footprint from the hit's differentials using a MIP map, a pyramid of
+// successively halved copies of the image
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    wrap: WrapMode,
+    filter: FilterMode,
+}
+
+#[allow(dead_code)]
+impl ImageTexture {
+    pub fn new(
+        width: usize,
+        height: usize,
+        texels: Vec<Colour>,
+        wrap: WrapMode,
+        filter: FilterMode,
+    ) -> ImageTexture {
+        assert_eq!(texels.len(), width * height);
+
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(level) = levels.last().filter(|level| level.width * level.height > 1) {
+            levels.push(level.downsample());
+        }
+
+        ImageTexture {
+            levels,
+            wrap,
+            filter,
+        }
+    }
+
+    // Images are usually stored sRGB encoded, so `srgb` decodes them to linear colours. Data like
+    // roughness maps should be loaded with it off
+    pub fn load(
+        path: &Path,
+        wrap: WrapMode,
+        filter: FilterMode,
+        srgb: bool,
+    ) -> Result<ImageTexture, TextureError> {
+        let image = image::open(path)
+            .map_err(|err| TextureError(path.to_path_buf(), err))?
+            .to_rgb();
+        let decode = |value: u8| {
+            let value = value as f64 / 255.0;
+            if srgb {
+                srgb_to_linear(value)
+            } else {
+                value
+            }
+        };
+        let texels = image
+            .pixels()
+            .map(|pixel| Colour::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])))
+            .collect();
+
+        Ok(ImageTexture::new(
+            image.width() as usize,
+            image.height() as usize,
+            texels,
+            wrap,
+            filter,
+        ))
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    // Interpolates the full resolution image at `uv`
+    pub fn bilerp(&self, uv: &Vec2) -> Colour {
+        self.levels[0].bilerp(uv, self.wrap)
+    }
+
+    // Filters the image over the footprint spanned by `dst0` and `dst1` around `uv`
+    pub fn lookup(&self, uv: &Vec2, dst0: &Vec2, dst1: &Vec2) -> Colour {
+        match self.filter {
+            FilterMode::Bilinear => self.bilerp(uv),
+            FilterMode::Trilinear => {
+                let width = 2.0
+                    * dst0
+                        .x
+                        .abs()
+                        .max(dst0.y.abs())
+                        .max(dst1.x.abs())
+                        .max(dst1.y.abs());
+                self.trilinear(uv, width)
+            }
+            FilterMode::Ewa => self.ewa(uv, dst0, dst1),
+        }
+    }
+
+    // Level whose texels are `width` apart in UVs, fractional between levels
+    fn level_for_width(&self, width: f32) -> f32 {
+        let resolution = self.width().max(self.height()) as f32;
+
+        (width * resolution).max(1e-8).log2()
+    }
+
+    fn trilinear(&self, uv: &Vec2, width: f32) -> Colour {
+        let level = self.level_for_width(width);
+        let top = self.levels.len() - 1;
+        if level <= 0.0 {
+            return self.bilerp(uv);
+        }
+        if level >= top as f32 {
+            return self.levels[top].bilerp(uv, self.wrap);
+        }
+
+        let fine = level.floor() as usize;
+        let below = self.levels[fine].bilerp(uv, self.wrap);
+        let above = self.levels[fine + 1].bilerp(uv, self.wrap);
+        below.lerp(above, (level - fine as f32) as f64)
+    }
+
+    // Picks levels by the minor axis of the footprint, so the ellipse covers a handful of texels
+    fn ewa(&self, uv: &Vec2, dst0: &Vec2, dst1: &Vec2) -> Colour {
+        let (mut major, mut minor) = (*dst0, *dst1);
+        if major.mag_sq() < minor.mag_sq() {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let major_length = major.mag();
+        let mut minor_length = minor.mag();
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor *= scale;
+            minor_length *= scale;
+        }
+        if minor_length == 0.0 {
+            return self.bilerp(uv);
+        }
+
+        let level = self.level_for_width(minor_length).max(0.0);
+        let top = self.levels.len() - 1;
+        let fine = level.floor() as usize;
+        if fine >= top {
+            return self.levels[top].bilerp(uv, self.wrap);
+        }
+
+        let below = self.levels[fine].ewa(uv, &major, &minor, self.wrap);
+        let above = self.levels[fine + 1].ewa(uv, &major, &minor, self.wrap);
+        below.lerp(above, (level - fine as f32) as f64)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        let differentials = &rec.differentials;
+        let dst0 = Vec2::new(differentials.dudx, differentials.dvdx);
+        let dst1 = Vec2::new(differentials.dudy, differentials.dvdy);
+
+        self.lookup(&rec.uv, &dst0, &dst1)
+    }
+}
+
+// Where a procedural texture is evaluated. UVs are treated as points with z = 0
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureSpace {
+    Uv,
+    Object,
+    World,
+}
+
+// Finds the point a procedural texture is evaluated at, from the texture's space through
+// `to_texture`, which places and scales the pattern
+pub struct TextureMapping {
+    space: TextureSpace,
+    to_texture: Mat4,
+}
+
+impl TextureMapping {
+    pub fn new(space: TextureSpace, to_texture: Mat4) -> TextureMapping {
+        TextureMapping { space, to_texture }
+    }
+
+    pub fn map(&self, rec: &IntersectRecord) -> Vec3 {
+        let point = match self.space {
+            TextureSpace::Uv => Vec3::new(rec.uv.x, rec.uv.y, 0.0),
+            TextureSpace::Object => rec.object_point,
+            TextureSpace::World => rec.point,
+        };
+
+        transform_point(&self.to_texture, point)
+    }
+}
+
+// Alternates between two colours on a unit grid
+pub struct Checkerboard {
+    mapping: TextureMapping,
+    colours: [Colour; 2],
+}
+
+impl Checkerboard {
+    pub fn new(mapping: TextureMapping, colours: [Colour; 2]) -> Checkerboard {
+        Checkerboard { mapping, colours }
+    }
+}
+
+impl Texture for Checkerboard {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        let p = self.mapping.map(rec);
+        let parity = (p.x.floor() + p.y.floor() + p.z.floor()) as i64 & 1;
+
+        self.colours[parity as usize]
+    }
+}
+
+// Blends between two colours by fractal Perlin noise. Turbulence gives billowing, creased
+// patterns, and otherwise the noise is smooth like clouds
+pub struct Noise {
+    mapping: TextureMapping,
+    colours: [Colour; 2],
+    perlin: Perlin,
+    octaves: u32,
+    roughness: f32,
+    turbulence: bool,
+}
+
+impl Noise {
+    pub fn new(
+        mapping: TextureMapping,
+        colours: [Colour; 2],
+        seed: u64,
+        octaves: u32,
+        roughness: f32,
+        turbulence: bool,
+    ) -> Noise {
+        Noise {
+            mapping,
+            colours,
+            perlin: Perlin::new(seed),
+            octaves,
+            roughness,
+            turbulence,
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        let p = self.mapping.map(rec);
+        let t = if self.turbulence {
+            self.perlin.turbulence(&p, self.roughness, self.octaves)
+        } else {
+            0.5 + 0.5 * self.perlin.fbm(&p, self.roughness, self.octaves)
+        };
+
+        self.colours[0].lerp(self.colours[1], t.clamp(0.0, 1.0) as f64)
+    }
+}
+
+// Veins of the second colour from bands along y, bent by fractal noise scaled by `variation`
+pub struct Marble {
+    mapping: TextureMapping,
+    colours: [Colour; 2],
+    perlin: Perlin,
+    octaves: u32,
+    roughness: f32,
+    variation: f32,
+}
+
+impl Marble {
+    pub fn new(
+        mapping: TextureMapping,
+        colours: [Colour; 2],
+        seed: u64,
+        octaves: u32,
+        roughness: f32,
+        variation: f32,
+    ) -> Marble {
+        Marble {
+            mapping,
+            colours,
+            perlin: Perlin::new(seed),
+            octaves,
+            roughness,
+            variation,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        let p = self.mapping.map(rec);
+        let bands = p.y + self.variation * self.perlin.fbm(&p, self.roughness, self.octaves);
+        // Sharpen the bands into thin veins
+        let t = (0.5 + 0.5 * bands.sin()).powi(4);
+
+        self.colours[0].lerp(self.colours[1], t as f64)
+    }
+}
+
+// Cellular pattern blending from the first colour at each cell's centre to the second a unit away
+pub struct Cellular {
+    mapping: TextureMapping,
+    colours: [Colour; 2],
+    seed: u64,
+}
+
+impl Cellular {
+    pub fn new(mapping: TextureMapping, colours: [Colour; 2], seed: u64) -> Cellular {
+        Cellular {
+            mapping,
+            colours,
+            seed,
+        }
+    }
+}
+
+impl Texture for Cellular {
+    fn evaluate(&self, rec: &IntersectRecord) -> Colour {
+        let distance = worley(&self.mapping.map(rec), self.seed);
+
+        self.colours[0].lerp(self.colours[1], distance.min(1.0) as f64)
+    }
+}
+
+// Inverse of the sRGB transfer function
+#[inline]
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
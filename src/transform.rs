@@ -0,0 +1,66 @@
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+#[allow(dead_code)]
+pub fn translate(delta: Vec3) -> Mat4 {
+    Mat4::from_translation(delta)
+}
+
+#[allow(dead_code)]
+pub fn scale(factors: Vec3) -> Mat4 {
+    Mat4::from_nonuniform_scale(Vec4::new(factors.x, factors.y, factors.z, 1.0))
+}
+
+// Rotation of `theta` degrees counter-clockwise around `axis`
+#[allow(dead_code)]
+pub fn rotate(theta: f32, axis: Vec3) -> Mat4 {
+    let a = axis.normalized();
+    let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+    let t = 1.0 - cos_theta;
+
+    Mat4::new(
+        Vec4::new(
+            a.x * a.x * t + cos_theta,
+            a.x * a.y * t + a.z * sin_theta,
+            a.x * a.z * t - a.y * sin_theta,
+            0.0,
+        ),
+        Vec4::new(
+            a.x * a.y * t - a.z * sin_theta,
+            a.y * a.y * t + cos_theta,
+            a.y * a.z * t + a.x * sin_theta,
+            0.0,
+        ),
+        Vec4::new(
+            a.x * a.z * t + a.y * sin_theta,
+            a.y * a.z * t - a.x * sin_theta,
+            a.z * a.z * t + cos_theta,
+            0.0,
+        ),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    )
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn transform_point(m: &Mat4, p: Vec3) -> Vec3 {
+    let p = *m * Vec4::new(p.x, p.y, p.z, 1.0);
+
+    if p.w == 1.0 || p.w == 0.0 {
+        p.xyz()
+    } else {
+        p.xyz() / p.w
+    }
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn transform_vector(m: &Mat4, v: Vec3) -> Vec3 {
+    (*m * Vec4::new(v.x, v.y, v.z, 0.0)).xyz()
+}
+
+// Normals transform by the inverse transpose, so this takes the inverse transform directly
+#[allow(dead_code)]
+#[inline]
+pub fn transform_normal(inverse: &Mat4, n: Vec3) -> Vec3 {
+    (inverse.transposed() * Vec4::new(n.x, n.y, n.z, 0.0)).xyz()
+}
@@ -0,0 +1,314 @@
+use crate::bounds::Bounds3;
+use crate::colour::Colour;
+use crate::intersectable::{IntersectRecord, Intersectable, SurfaceDifferentials};
+use crate::material::MaterialID;
+use crate::ray::Ray;
+use crate::shape::Shape;
+use crate::transform::{transform_normal, transform_point, transform_vector};
+use crate::utils::{
+    create_coordinates_system, transform_swaps_handedness, uniform_sample_triangle,
+};
+use std::sync::Arc;
+use ultraviolet::{Mat4, Vec2, Vec3};
+
+// Shared vertex data for a set of triangles, stored in world space
+pub struct TriangleMesh {
+    pub indices: Vec<u32>,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub colours: Vec<Colour>,
+
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    reverse_orientation: bool,
+    transform_swaps_handedness: bool,
+}
+
+impl TriangleMesh {
+    // Normals, UVs and colours are optional, but when present there must be one per position
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        object_to_world: Mat4,
+        world_to_object: Mat4,
+        indices: Vec<u32>,
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        colours: Vec<Colour>,
+        reverse_orientation: bool,
+    ) -> TriangleMesh {
+        assert_eq!(
+            indices.len() % 3,
+            0,
+            "Triangle mesh indices must come in threes"
+        );
+        assert!(
+            indices
+                .iter()
+                .all(|index| (*index as usize) < positions.len()),
+            "Triangle mesh indices must refer to existing positions"
+        );
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        assert!(colours.is_empty() || colours.len() == positions.len());
+
+        let positions = positions
+            .iter()
+            .map(|p| transform_point(&object_to_world, *p))
+            .collect();
+        let normals = normals
+            .iter()
+            .map(|n| transform_normal(&world_to_object, *n).normalized())
+            .collect();
+        let transform_swaps_handedness = transform_swaps_handedness(&object_to_world);
+
+        TriangleMesh {
+            indices,
+            positions,
+            normals,
+            uvs,
+            colours,
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+            transform_swaps_handedness,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangles(mesh: &Arc<TriangleMesh>, material_id: MaterialID) -> Vec<Box<dyn Shape>> {
+        (0..mesh.triangle_count())
+            .map(|index| {
+                Box::new(Triangle::new(mesh.clone(), index, material_id)) as Box<dyn Shape>
+            })
+            .collect()
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+    material_id: MaterialID,
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<TriangleMesh>, index: usize, material_id: MaterialID) -> Triangle {
+        Triangle {
+            mesh,
+            index,
+            material_id,
+        }
+    }
+
+    #[inline]
+    fn vertex_indices(&self) -> [usize; 3] {
+        let i = self.index * 3;
+        [
+            self.mesh.indices[i] as usize,
+            self.mesh.indices[i + 1] as usize,
+            self.mesh.indices[i + 2] as usize,
+        ]
+    }
+
+    #[inline]
+    fn positions(&self) -> [Vec3; 3] {
+        let [v0, v1, v2] = self.vertex_indices();
+        let positions = &self.mesh.positions;
+
+        [positions[v0], positions[v1], positions[v2]]
+    }
+
+    // Meshes without UVs are parameterised like pbrt's, as if the vertices were at (0, 0), (1, 0)
+    // and (1, 1)
+    #[inline]
+    fn uvs(&self) -> [Vec2; 3] {
+        if self.mesh.uvs.is_empty() {
+            return [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(1.0, 1.0),
+            ];
+        }
+        let [v0, v1, v2] = self.vertex_indices();
+        let uvs = &self.mesh.uvs;
+
+        [uvs[v0], uvs[v1], uvs[v2]]
+    }
+
+    // Record for the point with barycentric coordinates `b0`, `b1` and `b2`, facing the front.
+    // The shading normal interpolates the vertex normals if the mesh has them, and the face normal
+    // is turned to the same side. Vertex normals are transformed as normals, so only reversing the
+    // orientation flips them
+    fn record(&self, b0: f32, b1: f32, b2: f32) -> IntersectRecord {
+        let [p0, p1, p2] = self.positions();
+        let [uv0, uv1, uv2] = self.uvs();
+
+        let mut normal = (p1 - p0).cross(p2 - p0).normalized();
+        if self.mesh.reverse_orientation ^ self.mesh.transform_swaps_handedness {
+            normal = -normal;
+        }
+
+        let mut shading_normal = normal;
+        if !self.mesh.normals.is_empty() {
+            let [v0, v1, v2] = self.vertex_indices();
+            let normals = &self.mesh.normals;
+            let interpolated = normals[v0] * b0 + normals[v1] * b1 + normals[v2] * b2;
+            if interpolated.mag_sq() > 0.0 {
+                shading_normal = interpolated.normalized();
+                if self.mesh.reverse_orientation {
+                    shading_normal = -shading_normal;
+                }
+                if normal.dot(shading_normal) < 0.0 {
+                    normal = -normal;
+                }
+            }
+        }
+
+        // Solve for the derivatives along the edges from the third vertex
+        let duv02 = uv0 - uv2;
+        let duv12 = uv1 - uv2;
+        let dp02 = p0 - p2;
+        let dp12 = p1 - p2;
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        let (mut dpdu, mut dpdv) = (Vec3::zero(), Vec3::zero());
+        if determinant != 0.0 {
+            let inv_determinant = 1.0 / determinant;
+            dpdu = (dp02 * duv12.y - dp12 * duv02.y) * inv_determinant;
+            dpdv = (dp12 * duv02.x - dp02 * duv12.x) * inv_determinant;
+        }
+        let area_sq = dpdu.cross(dpdv).mag_sq();
+        if area_sq == 0.0 || !area_sq.is_finite() {
+            let (s, t) = create_coordinates_system(&normal);
+            dpdu = s;
+            dpdv = t;
+        }
+
+        let point = p0 * b0 + p1 * b1 + p2 * b2;
+        let vertex_colour = if self.mesh.colours.is_empty() {
+            None
+        } else {
+            let [v0, v1, v2] = self.vertex_indices();
+            let colours = &self.mesh.colours;
+            Some(colours[v0] * b0 as f64 + colours[v1] * b1 as f64 + colours[v2] * b2 as f64)
+        };
+
+        IntersectRecord {
+            point,
+            object_point: transform_point(&self.mesh.world_to_object, point),
+            normal,
+            shading_normal,
+            uv: uv0 * b0 + uv1 * b1 + uv2 * b2,
+            dpdu,
+            dpdv,
+            object_dpdu: transform_vector(&self.mesh.world_to_object, dpdu),
+            object_dpdv: transform_vector(&self.mesh.world_to_object, dpdv),
+            differentials: SurfaceDifferentials::default(),
+            vertex_colour,
+            front_face: true,
+            material_id: self.material_id,
+        }
+    }
+}
+
+impl Intersectable for Triangle {
+    // Möller–Trumbore intersection
+    fn intersect(&self, ray: &Ray, _test_alpha_textures: bool) -> Option<(IntersectRecord, f32)> {
+        let [p0, p1, p2] = self.positions();
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        // Only rays parallel to the triangle are rejected outright, as any fixed threshold would
+        // depend on the size of the triangle
+        if determinant == 0.0 {
+            return None;
+        }
+        let inv_determinant = 1.0 / determinant;
+
+        let t = ray.origin - p0;
+        let b1 = t.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let q = t.cross(edge1);
+        let b2 = ray.direction.dot(q) * inv_determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t_hit = edge2.dot(q) * inv_determinant;
+        if t_hit <= ray.t_min || t_hit > ray.t_max {
+            return None;
+        }
+
+        let mut rec = self.record(1.0 - b1 - b2, b1, b2);
+        rec.front_face = ray.direction.dot(rec.normal) < 0.0;
+
+        Some((rec, t_hit))
+    }
+}
+
+impl Shape for Triangle {
+    fn object_bounds(&self) -> Bounds3 {
+        let [p0, p1, p2] = self.positions();
+        let p0 = transform_point(&self.mesh.world_to_object, p0);
+        let p1 = transform_point(&self.mesh.world_to_object, p1);
+        let p2 = transform_point(&self.mesh.world_to_object, p2);
+
+        Bounds3::new(p0, p0).union_point(p1).union_point(p2)
+    }
+
+    // Vertices are already in world space, so this avoids a round trip through the transform
+    fn world_bounds(&self) -> Bounds3 {
+        let [p0, p1, p2] = self.positions();
+
+        Bounds3::new(p0, p0).union_point(p1).union_point(p2)
+    }
+
+    #[inline]
+    fn object_to_world(&self) -> &Mat4 {
+        &self.mesh.object_to_world
+    }
+
+    #[inline]
+    fn world_to_object(&self) -> &Mat4 {
+        &self.mesh.world_to_object
+    }
+
+    #[inline]
+    fn reverse_orientation(&self) -> bool {
+        self.mesh.reverse_orientation
+    }
+
+    #[inline]
+    fn transform_swaps_handedness(&self) -> bool {
+        self.mesh.transform_swaps_handedness
+    }
+
+    #[inline]
+    fn material_id(&self) -> MaterialID {
+        self.material_id
+    }
+
+    fn surface(&self) -> *const () {
+        Arc::as_ptr(&self.mesh) as *const ()
+    }
+
+    fn area(&self) -> f32 {
+        let [p0, p1, p2] = self.positions();
+
+        0.5 * (p1 - p0).cross(p2 - p0).mag()
+    }
+
+    fn sample(&self, u: &Vec2) -> IntersectRecord {
+        let (b0, b1) = uniform_sample_triangle(u);
+
+        self.record(b0, b1, 1.0 - b0 - b1)
+    }
+}
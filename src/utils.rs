@@ -1,11 +1,11 @@
-use rand::prelude::ThreadRng;
 use rand::Rng;
 use std::f32::consts::PI;
 use ultraviolet::Mat4;
+use ultraviolet::Vec2;
 use ultraviolet::Vec3;
 
 #[allow(dead_code)]
-pub fn random_unit_vector(rng: &mut ThreadRng) -> Vec3 {
+pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
     let z: f32 = rng.gen_range(-1.0, 1.0);
     let r = (1.0 - z.powi(2)).sqrt();
     let theta = rng.gen_range(0.0, 2.0 * PI);
@@ -15,15 +15,38 @@ pub fn random_unit_vector(rng: &mut ThreadRng) -> Vec3 {
 
 #[allow(dead_code)]
 pub fn transform_swaps_handedness(m: &Mat4) -> bool {
-    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+    linear_determinant(m) < 0.0
+}
+
+// Determinant of the upper 3x3 part of the transform, how much it scales volumes
+pub fn linear_determinant(m: &Mat4) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
         - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
-        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
 
-    det < 0.0
+// Scale factor of a transform made only of rotations, reflections, translations and uniform
+// scales, which keep shapes the same shape, or `None` if it stretches them
+pub fn uniform_scale(m: &Mat4) -> Option<f32> {
+    let axes = [0, 1, 2].map(|i| m.cols[i].xyz());
+    let scale_sq = axes[0].mag_sq();
+    let tolerance = 1e-4 * scale_sq;
+    let same_lengths = axes
+        .iter()
+        .all(|axis| (axis.mag_sq() - scale_sq).abs() <= tolerance);
+    let perpendicular = axes[0].dot(axes[1]).abs() <= tolerance
+        && axes[1].dot(axes[2]).abs() <= tolerance
+        && axes[0].dot(axes[2]).abs() <= tolerance;
+
+    if same_lengths && perpendicular {
+        Some(scale_sq.sqrt())
+    } else {
+        None
+    }
 }
 
 #[allow(dead_code)]
-pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
+pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
     let s = rand_distr::UnitSphere;
     let v: [f32; 3] = rng.sample(s);
 
@@ -33,7 +56,7 @@ pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
 #[allow(dead_code)]
 #[inline]
 pub fn gamma(n: i32) -> f32 {
-    (n as f32 * std::f32::EPSILON) / (1.0 - n as f32 * std::f32::EPSILON)
+    (n as f32 * f32::EPSILON) / (1.0 - n as f32 * f32::EPSILON)
 }
 
 #[allow(dead_code)]
@@ -111,3 +134,69 @@ pub fn uniform_sample_hemisphere(r1: f32, r2: f32) -> Vec3 {
 
     Vec3::new(x, y, r1)
 }
+
+#[allow(dead_code)]
+#[inline]
+pub fn uniform_sample_sphere(u: &Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z.powi(2)).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn concentric_sample_disk(u: &Vec2) -> Vec2 {
+    let offset = *u * 2.0 - Vec2::new(1.0, 1.0);
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vec2::zero();
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, PI / 4.0 * (offset.y / offset.x))
+    } else {
+        (offset.y, PI / 2.0 - PI / 4.0 * (offset.x / offset.y))
+    };
+
+    Vec2::new(r * theta.cos(), r * theta.sin())
+}
+
+// Directions around +z with a pdf of cos(theta) / PI
+#[allow(dead_code)]
+#[inline]
+pub fn cosine_sample_hemisphere(u: &Vec2) -> Vec3 {
+    let d = concentric_sample_disk(u);
+    let z = (1.0 - d.x.powi(2) - d.y.powi(2)).max(0.0).sqrt();
+
+    Vec3::new(d.x, d.y, z)
+}
+
+// Weight for a sample from a strategy with `f_pdf`, combined with another strategy with `g_pdf`
+#[inline]
+pub fn power_heuristic(f_pdf: f32, g_pdf: f32) -> f32 {
+    let f = f_pdf.powi(2);
+    let g = g_pdf.powi(2);
+    if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
+}
+
+// Returns the first two barycentric coordinates of a uniformly distributed point
+#[allow(dead_code)]
+#[inline]
+pub fn uniform_sample_triangle(u: &Vec2) -> (f32, f32) {
+    let su0 = u.x.sqrt();
+
+    (1.0 - su0, u.y * su0)
+}
+
+// SplitMix64 finaliser, for hashing bits into well mixed ones
+#[inline]
+pub fn mix_bits(mut v: u64) -> u64 {
+    v = (v ^ (v >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    v = (v ^ (v >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    v ^ (v >> 31)
+}